@@ -0,0 +1,149 @@
+use reqwest::header::CONTENT_TYPE;
+use serde::Deserialize;
+use serenity::{
+    async_trait,
+    model::{channel::Attachment, id::GuildId},
+    prelude::TypeMapKey,
+};
+use songbird::{Event, EventContext, EventHandler};
+use std::{
+    error::Error,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+};
+use tracing::{debug, warn};
+
+/// Extensions accepted when the content type doesn't tell
+const EXTENSIONS: &[&str] = &[
+    "aac", "flac", "m4a", "mka", "mkv", "mp3", "mp4", "ogg", "opus", "wav", "webm", "wma",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AttachmentConfig {
+    /// Attachments are downloaded here, in a directory for each guild, and deleted
+    /// once played or when the bot leaves
+    pub dir: PathBuf,
+    /// MiB, larger attachments aren't played
+    pub max_size: u64,
+}
+
+impl Default for AttachmentConfig {
+    fn default() -> Self {
+        Self {
+            dir: "audio_cache/attachments".into(),
+            max_size: 25,
+        }
+    }
+}
+
+impl TypeMapKey for AttachmentConfig {
+    type Value = AttachmentConfig;
+}
+
+impl AttachmentConfig {
+    /// Where the guild's attachments are downloaded
+    pub fn guild_dir(&self, guild: GuildId) -> PathBuf {
+        self.dir.join(guild.0.to_string())
+    }
+}
+
+/// Audio or video by the content type, by the extension when it's missing or generic
+fn is_audio(content_type: Option<&str>, filename: &str) -> bool {
+    match content_type {
+        Some(t) if t.starts_with("audio/") || t.starts_with("video/") => true,
+        Some("application/ogg") => true,
+        None | Some("application/octet-stream") => filename
+            .rsplit('.')
+            .next()
+            .map_or(false, |e| EXTENSIONS.contains(&e.to_lowercase().as_str())),
+        Some(_) => false,
+    }
+}
+
+/// Saves the attachment in the download directory, failing with a message for the user
+/// if it's too big or not audio
+pub async fn download(
+    attachment: &Attachment,
+    guild: GuildId,
+    config: &AttachmentConfig,
+) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    let max = config.max_size * 1024 * 1024;
+    if attachment.size > max {
+        return Err(format!("it's larger than {} MiB", config.max_size).into());
+    }
+
+    let mut response = reqwest::get(&attachment.url).await?.error_for_status()?;
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            v.split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_lowercase()
+        });
+    if !is_audio(content_type.as_deref(), &attachment.filename) {
+        return Err("it isn't an audio file".into());
+    }
+
+    let dir = config.guild_dir(guild);
+    fs::create_dir_all(&dir).await?;
+    // Named by id, the uploader picks the file name
+    let path = dir.join(attachment.id.0.to_string());
+    let res = save(&mut response, &path, max).await;
+    if res.is_err() {
+        remove(&path).await;
+    }
+    res.map(|_| path)
+}
+
+async fn save(
+    response: &mut reqwest::Response,
+    path: &Path,
+    max: u64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut file = File::create(path).await?;
+    let mut size = 0;
+    while let Some(chunk) = response.chunk().await? {
+        size += chunk.len() as u64;
+        if size > max {
+            return Err(format!("it's larger than {} MiB", max / 1024 / 1024).into());
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+async fn remove(path: &Path) {
+    if let Err(e) = fs::remove_file(path).await {
+        debug!("Couldn't remove {}: {}", path.display(), e);
+    }
+}
+
+/// Deletes the downloads in `dir`, left behind by tracks that never ended
+pub async fn clear(dir: &Path) {
+    match fs::remove_dir_all(dir).await {
+        Ok(()) => debug!("Cleared {}", dir.display()),
+        Err(e) if e.kind() == ErrorKind::NotFound => (),
+        Err(e) => warn!("Couldn't clear {}: {}", dir.display(), e),
+    }
+}
+
+/// Deletes a downloaded attachment when its track ends or is skipped
+pub struct RemoveFile(pub PathBuf);
+
+#[async_trait]
+impl EventHandler for RemoveFile {
+    async fn act(&self, _: &EventContext<'_>) -> Option<Event> {
+        remove(&self.0).await;
+        Some(Event::Cancel)
+    }
+}
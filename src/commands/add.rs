@@ -1,186 +1,704 @@
-use super::{utils::*, TrackOwner};
-use serde_json::Value;
+use super::{
+    resolve::{self, open_raw, reconnect, Lazy, Resolved, Track},
+    utils::*,
+    TrackAudio, TrackEntry, TrackLive, TrackOwner, TrackPrefetch,
+};
+use crate::{
+    attachment::{self, AttachmentConfig, RemoveFile},
+    auth,
+    export::OpusSource,
+    lazy,
+    range::Range,
+    tap::Taps,
+    ytdl::{self, PlaylistConfig},
+    QueueEntry, SavedQueues,
+};
+use futures::{channel::oneshot, future::FutureExt};
 use serenity::{
+    async_trait,
     client::Context,
-    framework::standard::{macros::command, Args, CommandResult},
-    model::channel::Message,
+    framework::standard::{macros::command, Args, CommandResult, Delimiter},
+    model::{
+        channel::{Attachment, Message},
+        id::{ChannelId, GuildId, UserId},
+    },
 };
 use songbird::{
-    input::{cached::Compressed, Metadata},
-    Bitrate,
+    input::{cached::Compressed, Input, Metadata},
+    tracks::{TrackHandle, TrackQueue},
+    Bitrate, Event, EventContext, EventHandler, Songbird, TrackEvent,
+};
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use std::time::Duration;
 use tracing::{info, warn};
 
 #[cfg(feature = "cache")]
-use crate::cache::{self, TrackCache, TrackEndEvent, BITRATE};
-#[cfg(feature = "cache")]
-use songbird::{Event, TrackEvent};
+use crate::{
+    cache::{self, TrackCache, TrackEndEvent, BITRATE},
+    presets::Presets,
+};
 #[cfg(feature = "cache")]
 use tokio::{fs::File, io::AsyncReadExt};
 
+/// Results shown by `search`, as many as there are reactions to pick them with
+const SEARCH_RESULTS: usize = 10;
+
 #[command]
 #[aliases("a")]
 #[only_in(guilds)]
-#[min_args(1)]
-#[description = "Add song to queue"]
-// TODO: Handle playlists
-pub async fn add(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+#[description = "Add song, playlist, stream or attached audio files to queue, or the ones \
+attached to the message replied to. Links to audio files and radios are played without ytdl. \
+A time like `1:30` or `1:30-3:00` after the song plays part of it"]
+#[usage = "<url or search terms> [start[-end]]"]
+pub async fn add(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    add_args(ctx, msg, args).await;
+
+    Ok(())
+}
+
+/// What `add` does, returning the handles of the tracks it queued
+async fn add_args(ctx: &Context, msg: &Message, mut args: Args) -> Vec<TrackHandle> {
     let query = args
         .iter()
         .map(|a| a.unwrap_or("".to_owned()))
         .collect::<Vec<String>>()
         .join(" ");
+    let (query, range) = split_range(&query);
 
-    let (input, query_msg) = match if query.starts_with("http") {
-        (
-            msg.channel_id
-                .say(&ctx.http, format!("Adding {} to the queue", query))
-                .await,
-            songbird::ytdl(&query).await,
-        )
-    } else {
-        (
-            msg.channel_id
-                .say(&ctx.http, format!("Searching on Youtube {}", query))
-                .await,
-            songbird::input::ytdl_search(&query).await,
-        )
-    } {
-        (m, Ok(i)) => (i, m.unwrap()),
-        (_, Err(e)) => {
-            info!("Error creating input: {:?}", e);
+    let mut added = Vec::new();
+    if !msg.attachments.is_empty() {
+        added = add_attachments(ctx, msg, &msg.attachments, msg.author.id).await;
+    }
+    if query.is_empty() {
+        match &msg.referenced_message {
+            Some(r) if msg.attachments.is_empty() && !r.attachments.is_empty() => {
+                added = add_attachments(ctx, msg, &r.attachments, r.author.id).await
+            }
+            _ if msg.attachments.is_empty() => handle_message(
+                msg.channel_id
+                    .say(&ctx.http, "Add a link, search terms or an audio file")
+                    .await,
+            ),
+            _ => (),
+        }
+        return added;
+    }
+
+    added.extend(add_with(ctx, msg, None, &query, range).await);
+    added
+}
+
+/// Resolves the query with the named resolver, or the first one that handles it,
+/// and queues the result, returning the handles of the queued tracks
+async fn add_with(
+    ctx: &Context,
+    msg: &Message,
+    resolver: Option<&str>,
+    query: &str,
+    range: Option<Range>,
+) -> Vec<TrackHandle> {
+    // Credentials in the link stay out of the chat and the logs
+    let shown = auth::split(query).0;
+    let part = range.map(|r| format!(" ({})", r)).unwrap_or_default();
+    let query_msg = msg
+        .channel_id
+        .say(&ctx.http, format!("Adding {}{} to the queue", shown, part))
+        .await;
+
+    let resolver = match resolver {
+        Some(name) => resolve::get(name),
+        None => resolve::find(ctx, msg, query).await,
+    };
+    let resolved = match resolver {
+        Some(r) => {
+            info!("Resolving {} with {}", shown, r.name());
+            r.resolve(ctx, msg, query).await
+        }
+        None => Err("nothing can play it".into()),
+    };
+    if let Ok(m) = query_msg {
+        handle_message(m.delete(&ctx.http).await);
+    }
+
+    match resolved {
+        Ok(Resolved::Track(track)) => queue_lazy(ctx, msg, track, range)
+            .await
+            .into_iter()
+            .collect(),
+        Ok(Resolved::Playlist { title, entries }) => {
+            add_playlist(ctx, msg, title.as_deref(), entries).await
+        }
+        Err(e) => {
+            info!("Error resolving {}: {}", shown, e);
             handle_message(
                 msg.channel_id
-                    .say(&ctx.http, format!("Error: {:?}", e))
+                    .say(&ctx.http, format!("Couldn't add {}: {}", shown, e))
                     .await,
             );
+            Vec::new()
+        }
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+#[description = "Queue again what was queued when the bot left, with the same owners and \
+time ranges"]
+pub async fn restore(ctx: &Context, msg: &Message) -> CommandResult {
+    let saved = {
+        let mut write = ctx.data.write().await;
+        write
+            .get_mut::<SavedQueues>()
+            .and_then(|s| s.remove(&msg.guild_id.unwrap()))
+    };
+    let entries = match saved {
+        Some(e) => e,
+        None => {
+            msg.channel_id.say(&ctx.http, "Nothing to restore").await?;
             return Ok(());
         }
     };
 
-    enqueue(ctx, msg, input).await;
-    handle_message(query_msg.delete(&ctx.http).await);
+    for entry in entries {
+        // Downloads are deleted on leave, the upload plays from Discord instead
+        let resolver = entry.file.as_ref().map(|_| "ffmpeg");
+        for h in add_with(ctx, msg, resolver, &entry.url, entry.range).await {
+            set_owner(&h, UserId(entry.owner)).await;
+            if let Some(e) = h.typemap().write().await.get_mut::<TrackEntry>() {
+                e.file = entry.file.clone();
+            }
+        }
+    }
 
     Ok(())
 }
 
+/// Takes a trailing range out of the query, and the start time out of YouTube links.
+/// Bare seconds only count after a link, they could be part of the search terms.
+fn split_range(query: &str) -> (String, Option<Range>) {
+    let explicit = query.rfind(' ').and_then(|i| {
+        let (rest, last) = (query[..i].trim_end(), &query[i + 1..]);
+        if last.contains(':') || rest.starts_with("http") {
+            Some((rest, Range::parse(last)?))
+        } else {
+            None
+        }
+    });
+    let (query, range) = match explicit {
+        Some((q, r)) => (q, Some(r)),
+        None => (query, None),
+    };
+
+    if query.starts_with("http") {
+        let (url, start) = Range::split_url(query);
+        (url, range.or(start))
+    } else {
+        (query.to_owned(), range)
+    }
+}
+
 #[command]
-#[aliases("r", "addraw", "add-raw", "ar")]
+#[aliases("find", "yt")]
 #[only_in(guilds)]
 #[min_args(1)]
-#[description = "Add ffmpeg URI to the queue"]
-pub async fn raw(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
-    let query: String = args.single().unwrap();
-
-    let (input, query_msg) = match if {
-        query.starts_with("http")
-            || query.starts_with("rtmp")
-            || query.starts_with("ftp")
-            || query.starts_with("hls")
-            || query.starts_with("tcp")
-            || query.starts_with("udp")
-    } {
-        (
-            msg.channel_id
-                .say(&ctx.http, format!("Adding {} to the queue", query))
-                .await,
-            songbird::ffmpeg(&query).await,
-        )
-    } else {
-        handle_message(
-            msg.channel_id
-                .say(&ctx.http, format!("Invalid protocol"))
-                .await,
-        );
-        return Ok(());
-    } {
-        (m, Ok(i)) => (i, m.unwrap()),
-        (_, Err(e)) => {
-            info!("Error creating input: {:?}", e);
+#[description = "Search on Youtube and pick a result to add to the queue"]
+pub async fn search(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let query = args.rest().trim();
+    let mut results = match ytdl::search(query, SEARCH_RESULTS).await {
+        Ok(r) if !r.is_empty() => r,
+        Ok(_) => {
+            handle_message(
+                msg.channel_id
+                    .say(&ctx.http, format!("No results for {}", query))
+                    .await,
+            );
+            return Ok(());
+        }
+        Err(e) => {
+            info!("Error searching for {}: {}", query, e);
             handle_message(
                 msg.channel_id
-                    .say(&ctx.http, format!("Error: {:?}", e))
+                    .say(&ctx.http, format!("Error searching for {}", query))
                     .await,
             );
             return Ok(());
         }
     };
 
-    enqueue(ctx, msg, input).await;
-    handle_message(query_msg.delete(&ctx.http).await);
+    let options = results
+        .iter()
+        .map(|m| {
+            let mut details = Vec::new();
+            if let Some(a) = &m.artist {
+                details.push(a.clone());
+            }
+            if let Some(d) = m.duration {
+                details.push(format!("{}:{:02}", d.as_secs() / 60, d.as_secs() % 60));
+            }
+            format!(
+                "[{}]({}) {}",
+                m.title.as_deref().unwrap_or("?"),
+                m.source_url.as_deref().unwrap_or_default(),
+                details.join(", ")
+            )
+        })
+        .collect::<Vec<String>>();
+
+    match pick(ctx, msg, &format!("Results for {}", query), &options).await {
+        // The result already has what the queue shows, ytdl opens it when it plays
+        Some(i) => {
+            let entry = resolve::ytdl_entry(results.swap_remove(i));
+            queue_lazy(ctx, msg, entry, None).await;
+        }
+        None => handle_message(msg.channel_id.say(&ctx.http, "Nothing picked").await),
+    }
 
     Ok(())
 }
 
 #[command]
-#[aliases("i", "ice", "ai", "add-icecast")]
+#[aliases("pn", "next")]
 #[only_in(guilds)]
 #[min_args(1)]
-#[description = "Add icecast stream to the queue"]
-// TODO: Parse start time as SystemTime
-pub async fn icecast(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
-    use crate::icecast::FromIceJson;
-
-    let query: String = args.single().unwrap();
+#[description = "Add song or playlist to play right after the current one"]
+#[usage = "<url or search terms> [start[-end]]"]
+pub async fn playnext(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    add_at(ctx, msg, 1, args).await
+}
 
-    let (input, query_msg) = match if query.starts_with("http") {
-        (
-            msg.channel_id
-                .say(&ctx.http, format!("Adding {} to the queue", query))
-                .await,
-            {
-                let uri: http::uri::Uri = query.parse().unwrap();
-                let stats = format!(
-                    "{}://{}/status-json.xsl",
-                    uri.scheme_str().unwrap(),
-                    uri.authority().unwrap(),
-                );
-                let json: Value = reqwest::get(&stats).await?.json().await?;
-                songbird::ffmpeg(&query).await.and_then(|mut i| {
-                    i.metadata = Box::new(Metadata::from_ice_json(json, &query));
-                    Ok(i)
-                })
-            },
-        )
-    } else {
-        handle_message(
-            msg.channel_id
-                .say(&ctx.http, format!("Invalid protocol"))
-                .await,
-        );
-        return Ok(());
-    } {
-        (m, Ok(i)) => (i, m.unwrap()),
-        (_, Err(e)) => {
-            info!("Error creating input: {:?}", e);
+#[command]
+#[aliases("ins")]
+#[only_in(guilds)]
+#[min_args(2)]
+#[description = "Add song or playlist at a position in the queue, 1 is next"]
+#[usage = "<index> <url or search terms> [start[-end]]"]
+pub async fn insert(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    match args.single::<usize>() {
+        Ok(i) => {
+            let rest = Args::new(args.rest(), &[Delimiter::Single(' ')]);
+            add_at(ctx, msg, i.max(1), rest).await
+        }
+        Err(_) => {
             handle_message(
                 msg.channel_id
-                    .say(&ctx.http, format!("Error: {:?}", e))
+                    .say(&ctx.http, "Usage: insert <index> <url or search terms>")
                     .await,
             );
+            Ok(())
+        }
+    }
+}
+
+/// Adds like `add`, then moves what got added to `index` in the queue
+async fn add_at(ctx: &Context, msg: &Message, index: usize, args: Args) -> CommandResult {
+    if let Some(m) = &msg.member {
+        if !permission_check(ctx, m).await {
             return Ok(());
         }
+    } else {
+        return Ok(());
+    }
+
+    let added = add_args(ctx, msg, args)
+        .await
+        .iter()
+        .map(|t| t.uuid())
+        .collect::<HashSet<_>>();
+
+    let manager = songbird::get(ctx).await.unwrap().clone();
+    let lock = match manager.get(msg.guild_id.unwrap()) {
+        Some(l) => l,
+        None => return Ok(()),
     };
+    let tracks = lock.lock().await.queue().clone();
+    let moved = tracks.modify_queue(|queue| {
+        // The playing track stays where it is, even if it was just added
+        let mut moving = Vec::new();
+        let mut i = 1;
+        while i < queue.len() {
+            if added.contains(&queue[i].uuid()) {
+                moving.extend(queue.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        let at = index.min(queue.len());
+        let moved = !moving.is_empty();
+        for (n, t) in moving.into_iter().enumerate() {
+            queue.insert(at + n, t);
+        }
+        Some(at).filter(|_| moved)
+    });
 
-    enqueue(ctx, msg, input).await;
-    handle_message(query_msg.delete(&ctx.http).await);
+    // A lazy entry moved up next has to be opened before its turn
+    prefetch_next(&tracks).await;
+
+    if let Some(at) = moved {
+        handle_message(
+            msg.channel_id
+                .say(&ctx.http, format!("Queued at position {}", at))
+                .await,
+        );
+    }
 
     Ok(())
 }
 
-async fn enqueue(ctx: &Context, msg: &Message, input: songbird::input::Input) {
-    let guild = msg.guild(&ctx.cache).await.unwrap();
-    let guild_id = guild.id;
-    let channel_id = match guild
+#[command]
+#[aliases("r", "addraw", "add-raw", "ar")]
+#[only_in(guilds)]
+#[min_args(1)]
+#[description = "Add ffmpeg URI to the queue"]
+#[usage = "<uri> [start[-end]]"]
+pub async fn raw(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let (query, resolver) = resolve_preset(ctx, msg, args.single().unwrap()).await;
+    let range = args.single::<String>().ok().and_then(|r| Range::parse(&r));
+
+    add_with(ctx, msg, Some(resolver.unwrap_or("ffmpeg")), &query, range).await;
+
+    Ok(())
+}
+
+#[command]
+#[aliases("i", "ice", "ai", "add-icecast", "shoutcast", "sc")]
+#[only_in(guilds)]
+#[min_args(1)]
+#[description = "Add Icecast or Shoutcast stream to the queue, or pick one from a server"]
+pub async fn icecast(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let (query, resolver) = resolve_preset(ctx, msg, args.single().unwrap()).await;
+
+    add_with(ctx, msg, Some(resolver.unwrap_or("icecast")), &query, None).await;
+
+    Ok(())
+}
+
+/// URL of the guild's preset called `query` and the resolver its kind is played with,
+/// or `query` itself if there's none
+async fn resolve_preset(
+    ctx: &Context,
+    msg: &Message,
+    query: String,
+) -> (String, Option<&'static str>) {
+    #[cfg(feature = "cache")]
+    if !query.contains("://") {
+        let presets = {
+            let read = ctx.data.read().await;
+            read.get::<Presets>().cloned()
+        };
+        if let Some(p) = presets {
+            match p.get(msg.guild_id.unwrap(), &query).await {
+                Ok(Some(preset)) => return (preset.url, Some(preset.kind.resolver())),
+                Ok(None) => (),
+                Err(e) => warn!("Error reading presets: {}", e),
+            }
+        }
+    }
+    #[cfg(not(feature = "cache"))]
+    let _ = (ctx, msg);
+    (query, None)
+}
+
+/// Keeps a message up to date with the number of playlist entries queued
+struct Progress {
+    message: Option<Message>,
+    total: usize,
+    updated: Instant,
+}
+
+impl Progress {
+    async fn new(ctx: &Context, msg: &Message, name: &str, total: usize) -> Self {
+        let message = msg
+            .channel_id
+            .say(
+                &ctx.http,
+                format!("Adding {} entries from {} to the queue", total, name),
+            )
+            .await;
+        Self {
+            message: message
+                .map_err(|e| warn!("Could not send message: {}", e))
+                .ok(),
+            total,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Edits the message at most every two seconds, to stay clear of rate limits
+    async fn update(&mut self, ctx: &Context, added: usize) {
+        if self.updated.elapsed() < Duration::from_secs(2) {
+            return;
+        }
+        self.updated = Instant::now();
+        if let Some(m) = &mut self.message {
+            let text = format!("Adding playlist entries: {}/{}", added, self.total);
+            handle_message(m.edit(&ctx, |e| e.content(text)).await);
+        }
+    }
+
+    async fn finish(mut self, ctx: &Context, added: usize) {
+        if let Some(m) = &mut self.message {
+            let text = format!("Added {}/{} playlist entries", added, self.total);
+            handle_message(m.edit(&ctx, |e| e.content(text)).await);
+        }
+    }
+}
+
+/// Queues the entries of a playlist in order. Each one is only opened once the track
+/// before it starts playing.
+async fn add_playlist(
+    ctx: &Context,
+    msg: &Message,
+    title: Option<&str>,
+    mut entries: Vec<Lazy>,
+) -> Vec<TrackHandle> {
+    let max = {
+        let read = ctx.data.read().await;
+        read.get::<PlaylistConfig>()
+            .cloned()
+            .unwrap_or_default()
+            .max_entries
+    };
+    entries.truncate(max);
+    if voice_channel(ctx, msg).await.is_none() {
+        handle_message(msg.reply(&ctx, "not in a voice channel").await);
+        return Vec::new();
+    }
+    let name = title.unwrap_or("the playlist");
+    let mut progress = Progress::new(ctx, msg, name, entries.len()).await;
+
+    let mut added = Vec::new();
+    for entry in entries {
+        // Already reported, the other entries may still work
+        added.extend(queue_lazy(ctx, msg, entry, None).await);
+        progress.update(ctx, added.len()).await;
+    }
+    progress.finish(ctx, added.len()).await;
+    added
+}
+
+/// Queues a track with only what's known about it, playing only `range` of it.
+/// Tracks that fail to open are reported, and skipped when their turn comes.
+async fn queue_lazy(
+    ctx: &Context,
+    msg: &Message,
+    entry: Lazy,
+    range: Option<Range>,
+) -> Option<TrackHandle> {
+    let name = entry
+        .meta
+        .title
+        .clone()
+        .or_else(|| entry.meta.source_url.clone())
+        .unwrap_or_default();
+    let (task_ctx, channel, guild_id) = (ctx.clone(), msg.channel_id, msg.guild_id.unwrap());
+    let (tx, rx) = oneshot::channel::<TrackHandle>();
+    let pending = entry.open;
+    let open = async move {
+        let track = match pending.await {
+            Ok(t) => t,
+            Err(e) => {
+                info!("Error opening {}: {}", name, e);
+                handle_message(
+                    channel
+                        .say(&task_ctx.http, format!("Skipping {}: {}", name, e))
+                        .await,
+                );
+                return Err(e.to_string());
+            }
+        };
+        let handle = rx.await.map_err(|_| "the track wasn't queued".to_owned())?;
+        if let Some(l) = track.live {
+            handle.typemap().write().await.insert::<TrackLive>(l);
+        }
+        if let Some(f) = track.queued {
+            f(handle.clone());
+        }
+        // The whole track gets cached, only the range plays
+        let input = if track.compress {
+            compress(&task_ctx, &handle, track.input).await?
+        } else {
+            track.input
+        };
+        let input = match range {
+            Some(r) => r.wrap(input),
+            None => input,
+        };
+        // Tapping the opened input keeps Opus sources from being encoded again
+        let taps = {
+            let read = task_ctx.data.read().await;
+            read.get::<Taps>().cloned().unwrap()
+        };
+        Ok(taps.wrap(guild_id, input))
+    };
+
+    let mut meta = entry.meta;
+    if let (Some(r), Some(d)) = (range, meta.duration) {
+        meta.duration = Some(r.length(d));
+    }
+    let url = meta.source_url.clone();
+    let (input, prefetch) = lazy::input(meta, open.boxed());
+    let handle = enqueue_input(ctx, msg, input, range).await?;
+    // Cache hits play from disk, there's nothing to open
+    let opens = !handle.typemap().read().await.contains_key::<TrackAudio>();
+    {
+        let mut typemap = handle.typemap().write().await;
+        if opens {
+            typemap.insert::<TrackPrefetch>(prefetch.clone());
+        }
+        if let Some(url) = url {
+            typemap.insert::<TrackEntry>(QueueEntry {
+                url,
+                file: None,
+                owner: msg.author.id.0,
+                range,
+            });
+        }
+    }
+    let _ = tx.send(handle.clone());
+
+    // Nothing before it is going to start playing
+    let manager = songbird::get(ctx).await.unwrap().clone();
+    let next = match manager.get(msg.guild_id.unwrap()) {
+        Some(call) => call.lock().await.queue().len() <= 2,
+        None => false,
+    };
+    if opens && next {
+        prefetch.start();
+    }
+    Some(handle)
+}
+
+/// Opens the next entry in the queue when a track starts playing, so it's ready in time
+struct PrefetchNext {
+    manager: Arc<Songbird>,
+    guild_id: GuildId,
+}
+
+#[async_trait]
+impl EventHandler for PrefetchNext {
+    async fn act(&self, _: &EventContext<'_>) -> Option<Event> {
+        let queue = match self.manager.get(self.guild_id) {
+            Some(call) => call.lock().await.queue().clone(),
+            None => return None,
+        };
+        prefetch_next(&queue).await;
+        None
+    }
+}
+
+/// Starts opening the track after the playing one, if it's a lazy entry
+async fn prefetch_next(queue: &TrackQueue) {
+    if let Some(next) = queue.current_queue().get(1) {
+        if let Some(p) = next.typemap().read().await.get::<TrackPrefetch>() {
+            p.start();
+        }
+    }
+}
+
+/// Downloads the attachments and queues them owned by their uploader
+async fn add_attachments(
+    ctx: &Context,
+    msg: &Message,
+    attachments: &[Attachment],
+    uploader: UserId,
+) -> Vec<TrackHandle> {
+    let config = {
+        let read = ctx.data.read().await;
+        read.get::<AttachmentConfig>().cloned().unwrap_or_default()
+    };
+
+    let mut added = Vec::new();
+    for a in attachments {
+        let path = match attachment::download(a, msg.guild_id.unwrap(), &config).await {
+            Ok(p) => p,
+            Err(e) => {
+                handle_message(
+                    msg.channel_id
+                        .say(&ctx.http, format!("Can't play {}: {}", a.filename, e))
+                        .await,
+                );
+                continue;
+            }
+        };
+        let meta = Metadata {
+            title: Some(a.filename.clone()),
+            source_url: Some(a.url.clone()),
+            ..Default::default()
+        };
+        let file = path.to_string_lossy().into_owned();
+        let reconnect = reconnect(ctx, msg).await;
+        let (title, url) = (a.filename.clone(), a.url.clone());
+        let open = async move {
+            let (mut input, _) = open_raw(&file, None, reconnect)
+                .await
+                .map_err(|e| format!("{:?}", e))?;
+            input.metadata.title = Some(title);
+            input.metadata.source_url = Some(url);
+            // Already on disk, no need to keep it in memory too
+            Ok::<_, resolve::Error>(Track {
+                compress: false,
+                ..Track::new(input)
+            })
+        };
+        let entry = Lazy {
+            meta,
+            open: open.boxed(),
+        };
+        let handle = queue_lazy(ctx, msg, entry, None).await;
+
+        match handle {
+            Some(h) => {
+                set_owner(&h, uploader).await;
+                if let Some(e) = h.typemap().write().await.get_mut::<TrackEntry>() {
+                    e.file = Some(path.clone());
+                }
+                let _ = h.add_event(Event::Track(TrackEvent::End), RemoveFile(path));
+                added.push(h);
+            }
+            None => {
+                let _ = tokio::fs::remove_file(&path).await;
+            }
+        }
+    }
+    added
+}
+
+/// Gives the track to `owner`, also once it's restored
+async fn set_owner(handle: &TrackHandle, owner: UserId) {
+    let mut typemap = handle.typemap().write().await;
+    typemap.insert::<TrackOwner>(owner);
+    if let Some(e) = typemap.get_mut::<TrackEntry>() {
+        e.owner = owner.0;
+    }
+}
+
+/// The voice channel the author of `msg` is in
+async fn voice_channel(ctx: &Context, msg: &Message) -> Option<ChannelId> {
+    msg.guild(&ctx.cache)
+        .await?
         .voice_states
         .get(&msg.author.id)
         .and_then(|vs| vs.channel_id)
-    {
+}
+
+/// Adds the input to the guild's queue, returning the handle of the new track.
+/// A cached copy is played instead when there's one, with only `range` of it;
+/// other inputs come with their range applied.
+async fn enqueue_input(
+    ctx: &Context,
+    msg: &Message,
+    input: Input,
+    range: Option<Range>,
+) -> Option<TrackHandle> {
+    let guild_id = msg.guild_id.unwrap();
+    let channel_id = match voice_channel(ctx, msg).await {
         Some(id) => id,
         None => {
             handle_message(msg.reply(&ctx, "not in a voice channel").await);
-            return;
+            return None;
         }
     };
 
@@ -192,153 +710,174 @@ async fn enqueue(ctx: &Context, msg: &Message, input: songbird::input::Input) {
             info!("Couldn't join voice channel: {:?}", e);
             handle_message(
                 msg.channel_id
-                    .say(&ctx, "Couldn't join voice channel: {:?}")
+                    .say(&ctx, format!("Couldn't join voice channel: {:?}", e))
                     .await,
             );
-            return;
+            return None;
         }
     }
-    let meta = input.metadata.clone();
     #[cfg(feature = "cache")]
-    let mut comp = None;
+    let meta = input.metadata.clone();
     #[cfg(feature = "cache")]
     let cache = {
         let read = ctx.data.read().await;
         read.get::<TrackCache>().unwrap().clone()
     };
 
-    if let Some(_url) = meta.source_url {
-        #[cfg(feature = "cache")]
-        let input = if let Some(p) = cache.get(&_url).await.ok().flatten() {
-            use songbird::input::dca;
+    // Inputs without a source can't have been cached, they're queued as they are
+    #[cfg(feature = "cache")]
+    let cached = match &meta.source_url {
+        Some(url) => cache.get(url).await.ok().flatten().map(|p| (url, p)),
+        None => None,
+    };
+    #[cfg(feature = "cache")]
+    let (input, audio) = if let Some((url, p)) = cached {
+        use songbird::input::dca;
 
-            info!("Cache hit for {}", _url);
+        info!("Cache hit for {}", url);
 
-            let file = format!("audio_cache/{}", p);
-            let mut input = dca(&file).await.unwrap();
+        let file = format!("audio_cache/{}", p);
+        let mut input = dca(&file).await.unwrap();
 
-            // Metadata that doesn't fit in the standard dca1 stuff is in the extra
-            // field of the json metadata
-            // TODO: remove from cache and fetch again if fail
-            let extra_meta = {
-                let mut reader = handle_io(File::open(&file).await);
-                let mut header = [0u8; 4];
+        // Metadata that doesn't fit in the standard dca1 stuff is in the extra
+        // field of the json metadata
+        // TODO: remove from cache and fetch again if fail
+        let extra_meta = {
+            let mut reader = handle_io(File::open(&file).await);
+            let mut header = [0u8; 4];
 
-                handle_io(reader.read_exact(&mut header).await);
+            handle_io(reader.read_exact(&mut header).await);
 
-                if header != b"DCA1"[..] {
-                    tracing::error!("Invalid magic bytes");
-                    return;
-                }
+            if header != b"DCA1"[..] {
+                tracing::error!("Invalid magic bytes");
+                return None;
+            }
 
-                let size = handle_io(reader.read_i32_le().await);
-                if size < 2 {
-                    tracing::error!("Invalid metadata size");
-                    return;
-                };
+            let size = handle_io(reader.read_i32_le().await);
+            if size < 2 {
+                tracing::error!("Invalid metadata size");
+                return None;
+            };
 
-                let mut json = Vec::with_capacity(size as usize);
-                let mut json_reader = reader.take(size as u64);
+            let mut json = Vec::with_capacity(size as usize);
+            let mut json_reader = reader.take(size as u64);
 
-                handle_io(json_reader.read_to_end(&mut json).await);
-                let value = serde_json::from_slice(&json).unwrap_or_default();
-                cache::extra_meta(&value)
-            };
-            {
-                input.metadata = Box::new(Metadata {
-                    date: extra_meta.date,
-                    duration: extra_meta.duration,
-                    thumbnail: extra_meta.thumbnail,
-                    ..*input.metadata
-                })
-            }
-            input
-        } else if let Some(d) = meta.duration {
-            // TODO: Add config entry to limit lenght
-            if d <= Duration::from_secs(1200) {
-                match Compressed::new(input, Bitrate::BitsPerSecond(BITRATE as i32)) {
-                    Ok(compressed) => {
-                        comp = Some(compressed.new_handle());
-                        // Load the whole thing into RAM.
-                        // Audio artifacts appear when not doing this and loading the whole thing
-                        // in ram is usually cheaper than keeping ytdl and ffmpeg open
-                        let _ = compressed.raw.spawn_loader();
-                        compressed.into()
-                    }
-                    Err(e) => {
-                        warn!("Error creating compressed memory audio store: {:?}", e);
-                        handle_message(
-                            msg.channel_id
-                                .say(&ctx.http, format!("Error: {:?}", e))
-                                .await,
-                        );
-                        return;
-                    }
-                }
-            } else {
-                input
-            }
-        } else {
-            input
+            handle_io(json_reader.read_to_end(&mut json).await);
+            let value = serde_json::from_slice(&json).unwrap_or_default();
+            cache::extra_meta(&value)
         };
-
-        // TODO: Add config entry to limit lenght
-        #[cfg(not(feature = "cache"))]
-        let input = if meta.duration <= Some(Duration::from_secs(1200)) {
-            match Compressed::new(input, Bitrate::BitsPerSecond(128_000)) {
-                Ok(compressed) => {
-                    // Load the whole thing into RAM.
-                    // Audio artifacts appear when not doing this and loading the whole thing
-                    // in ram is usually cheaper than keeping ytdl and ffmpeg open
-                    let _ = compressed.raw.spawn_loader();
-                    compressed.into()
-                }
-                Err(e) => {
-                    warn!("Error creating compressed memory audio store: {:?}", e);
-                    handle_message(
-                        msg.channel_id
-                            .say(&ctx.http, format!("Error: {:?}", e))
-                            .await,
-                    );
-                    return;
-                }
-            }
-        } else {
-            input
+        {
+            input.metadata = Box::new(Metadata {
+                date: extra_meta.date,
+                duration: extra_meta.duration,
+                thumbnail: extra_meta.thumbnail,
+                ..*input.metadata
+            })
+        }
+        let input = match range {
+            Some(r) => r.wrap(input),
+            None => input,
+        };
+        // Played from disk instead of the opened input, which gets tapped once it opens
+        let taps = {
+            let read = ctx.data.read().await;
+            read.get::<Taps>().cloned().unwrap()
         };
+        let input = taps.wrap(guild_id, input);
+        (input, Some(OpusSource::Dca(file.into())))
+    } else {
+        (input, None)
+    };
+    #[cfg(not(feature = "cache"))]
+    let (input, audio) = {
+        let _ = range;
+        (input, None::<OpusSource>)
+    };
 
-        let manager = songbird::get(ctx).await.unwrap().clone();
+    let manager = songbird::get(ctx).await.unwrap().clone();
 
-        if manager.get(guild_id).is_none() {
-            let (_, join_result) = manager.join(guild_id, channel_id).await;
-            if let Err(_) = join_result {
-                handle_message(
-                    msg.channel_id
-                        .say(&ctx, "Couldn't join voice channel")
-                        .await,
-                );
-            }
+    if manager.get(guild_id).is_none() {
+        let (_, join_result) = manager.join(guild_id, channel_id).await;
+        if let Err(_) = join_result {
+            handle_message(
+                msg.channel_id
+                    .say(&ctx, "Couldn't join voice channel")
+                    .await,
+            );
         }
+    }
 
-        let locked = manager.get(guild_id).unwrap();
-        let mut call = locked.lock().await;
+    let locked = manager.get(guild_id).unwrap();
+    let mut call = locked.lock().await;
+    let (track, track_handle) = songbird::tracks::create_player(input);
+
+    let mut typemap = track_handle.typemap().write().await;
+    typemap.insert::<TrackOwner>(msg.author.id);
+    if let Some(a) = audio {
+        typemap.insert::<TrackAudio>(a);
+    }
 
-        let (track, track_handle) = songbird::tracks::create_player(input);
+    let _ = track_handle.add_event(
+        Event::Track(TrackEvent::Play),
+        PrefetchNext {
+            manager: manager.clone(),
+            guild_id,
+        },
+    );
+
+    call.enqueue(track);
+    drop(typemap);
+    Some(track_handle)
+}
 
-        let mut typemap = track_handle.typemap().write().await;
-        typemap.insert::<TrackOwner>(msg.author.id);
+/// Reads an opened input into memory when it's short enough, like YouTube videos, so
+/// it can be exported while it plays and gets cached once it ends
+async fn compress(ctx: &Context, handle: &TrackHandle, input: Input) -> Result<Input, String> {
+    // TODO: Add config entry to limit lenght
+    if input
+        .metadata
+        .duration
+        .map_or(true, |d| d > Duration::from_secs(1200))
+    {
+        return Ok(input);
+    }
+    #[cfg(feature = "cache")]
+    let bitrate = BITRATE as i32;
+    #[cfg(not(feature = "cache"))]
+    let bitrate = 128_000;
+
+    let compressed = match Compressed::new(input, Bitrate::BitsPerSecond(bitrate)) {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("Error creating compressed memory audio store: {:?}", e);
+            return Err(format!("{:?}", e));
+        }
+    };
+    let audio = OpusSource::Memory(compressed.new_handle());
+    handle.typemap().write().await.insert::<TrackAudio>(audio);
 
-        #[cfg(feature = "cache")]
-        if let Some(c) = comp {
-            let _ = track_handle.add_event(
+    #[cfg(feature = "cache")]
+    {
+        let cache = {
+            let read = ctx.data.read().await;
+            read.get::<TrackCache>().cloned()
+        };
+        if let Some(cache) = cache {
+            let _ = handle.add_event(
                 Event::Track(TrackEvent::End),
                 TrackEndEvent {
-                    cache: cache.clone(),
-                    compressed: c,
+                    cache,
+                    compressed: compressed.new_handle(),
                 },
             );
-        };
-
-        call.enqueue(track);
+        }
     }
+    #[cfg(not(feature = "cache"))]
+    let _ = ctx;
+
+    // Load the whole thing into RAM.
+    // Audio artifacts appear when not doing this and loading the whole thing
+    // in ram is usually cheaper than keeping ytdl and ffmpeg open
+    let _ = compressed.raw.spawn_loader();
+    Ok(compressed.into())
 }
@@ -1,4 +1,6 @@
-use super::{utils::*, CommandCounter, ShardManagerContainer, TrackOwner};
+use super::{utils::*, CommandCounter, ShardManagerContainer, TrackLive, TrackOwner};
+use crate::{date, icy::LiveMetadata};
+use chrono::Utc;
 use serenity::{
     builder::CreateMessage,
     client::{bridge::gateway::ShardId, Context},
@@ -111,7 +113,7 @@ pub async fn queue(ctx: &Context, msg: &Message) -> CommandResult {
         let text = {
             let mut out = Vec::with_capacity(queue.len());
             for (i, e) in queue.iter().enumerate().take(16) {
-                let meta = e.metadata().clone();
+                let meta = track_metadata(e).await;
                 let owner = if let Ok(o) = {
                     let read = e.typemap().read().await;
                     let user_id = read.get::<TrackOwner>().unwrap();
@@ -122,10 +124,12 @@ pub async fn queue(ctx: &Context, msg: &Message) -> CommandResult {
                     "?".to_owned()
                 };
                 out.push(format!(
-                    "`{}`: [{}]({}) {}\nRequested by {}",
+                    "`{}`: {} {}\nRequested by {}",
                     i,
-                    meta.title.unwrap_or("?".to_owned()),
-                    meta.source_url.unwrap_or("?".to_owned()),
+                    linked_title(
+                        meta.title.unwrap_or("?".to_owned()),
+                        meta.source_url.as_deref()
+                    ),
                     match meta.duration {
                         Some(d) => {
                             let s = d.as_secs();
@@ -170,13 +174,17 @@ async fn np(ctx: &Context, msg: &Message) -> CommandResult {
             handle_message(msg.channel_id.say(&ctx.http, "No song playing").await);
             return Ok(());
         };
-        let meta = current.metadata().clone();
-        let owner = {
+        let meta = track_metadata(&current).await;
+        let (owner, live) = {
             let read = current.typemap().read().await;
-            *read.get::<TrackOwner>().unwrap()
+            let live = match read.get::<TrackLive>() {
+                Some(l) => Some(l.read().await.clone()),
+                None => None,
+            };
+            (*read.get::<TrackOwner>().unwrap(), live)
         };
         let state = current.get_info().await.unwrap();
-        let mut message = format_metadata(&ctx, guild_id, meta, owner, state).await;
+        let mut message = format_metadata(&ctx, guild_id, meta, live, owner, state).await;
         handle_message(msg.channel_id.send_message(&ctx, |_| &mut message).await);
     } else {
         handle_message(msg.channel_id.say(&ctx, "Not in a voice channel").await);
@@ -189,6 +197,7 @@ async fn format_metadata<'a>(
     ctx: &Context,
     gid: id::GuildId,
     meta: songbird::input::Metadata,
+    live: Option<LiveMetadata>,
     author_id: id::UserId,
     state: Box<songbird::tracks::TrackState>,
 ) -> CreateMessage<'a> {
@@ -208,11 +217,23 @@ async fn format_metadata<'a>(
         if let Some(a) = meta.artist {
             out.push(("Artist/Channel", a, true));
         }
-        if let Some(a) = meta.date {
-            let mut d = a;
-            d.insert(6, '/');
-            d.insert(4, '/');
-            out.push(("Date", d, true));
+        if let Some(d) = meta.date.as_deref().and_then(date::parse) {
+            out.push(("Date", d.format("%-d %B %Y").to_string(), true));
+        }
+        if let Some(l) = &live {
+            let station = &l.station;
+            if let Some(n) = &station.name {
+                out.push(("Station", n.clone(), true));
+            }
+            if let Some(g) = &station.genre {
+                out.push(("Genre", g.clone(), true));
+            }
+            if let Some(n) = station.listeners {
+                out.push(("Listeners", n.to_string(), true));
+            }
+            if let Some(b) = station.bitrate {
+                out.push(("Bitrate", format!("{}kbps", b), true));
+            }
         }
         if out.len() != 0 {
             fields = Some(out)
@@ -254,7 +275,19 @@ async fn format_metadata<'a>(
     let desc = {
         use songbird::tracks::{LoopState, PlayMode};
         let mut out = String::new();
-        out.push_str(&meta.source_url.unwrap_or("".to_owned()));
+        if let Some(station) = live.map(|l| l.station) {
+            if let Some(d) = station.description {
+                out.push_str(&d);
+                out.push('\n');
+            }
+            if let Some(s) = station.started {
+                out.push_str(&format!(
+                    "Live for {}\n",
+                    date::format_duration(Utc::now() - s)
+                ));
+            }
+        }
+        out.push_str(source_link(meta.source_url.as_deref()).unwrap_or_default());
         if let Some(s) = progress_bar {
             out.push('\n');
             out.push_str(&s);
@@ -0,0 +1,100 @@
+use super::{utils::*, TrackAudio};
+use crate::export::UPLOAD_LIMIT;
+use serenity::{
+    client::Context,
+    framework::standard::{macros::command, Args, CommandResult},
+    model::channel::Message,
+};
+use tracing::warn;
+
+#[command]
+#[aliases("e", "dl", "download")]
+#[only_in(guilds)]
+#[description = "Upload a queued song as an Ogg Opus file, defaults to the current one"]
+#[usage = "[queue index]"]
+pub async fn export(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let index = args.single::<usize>().unwrap_or(0);
+    let manager = songbird::get(ctx).await.unwrap().clone();
+
+    let handle = if let Some(lock) = manager.get(msg.guild_id.unwrap()) {
+        let call = lock.lock().await;
+        call.queue().current_queue().get(index).cloned()
+    } else {
+        handle_message(msg.channel_id.say(&ctx, "Not in a voice channel").await);
+        return Ok(());
+    };
+    let handle = if let Some(h) = handle {
+        h
+    } else {
+        handle_message(
+            msg.channel_id
+                .say(&ctx, format!("No queue entry {}", index))
+                .await,
+        );
+        return Ok(());
+    };
+
+    let source = {
+        let read = handle.typemap().read().await;
+        read.get::<TrackAudio>().cloned()
+    };
+    let source = if let Some(s) = source {
+        s
+    } else {
+        handle_message(
+            msg.channel_id
+                .say(&ctx, "This song isn't cached, can't export it")
+                .await,
+        );
+        return Ok(());
+    };
+
+    let meta = handle.metadata().clone();
+    let status = msg.channel_id.say(&ctx, "Exporting...").await;
+
+    let ogg = {
+        let meta = meta.clone();
+        tokio::task::spawn_blocking(move || source.to_ogg(&meta)).await?
+    };
+    if let Ok(m) = status {
+        handle_message(m.delete(&ctx).await);
+    }
+
+    let ogg = match ogg {
+        Ok(o) => o,
+        Err(e) => {
+            warn!("Error exporting track: {}", e);
+            handle_message(msg.channel_id.say(&ctx, format!("Error: {}", e)).await);
+            return Ok(());
+        }
+    };
+    if ogg.len() > UPLOAD_LIMIT {
+        handle_message(
+            msg.channel_id
+                .say(
+                    &ctx,
+                    format!(
+                        "The file is {}MiB, over Discord's {}MiB limit",
+                        ogg.len() / 1024 / 1024,
+                        UPLOAD_LIMIT / 1024 / 1024
+                    ),
+                )
+                .await,
+        );
+        return Ok(());
+    }
+
+    let name = format!(
+        "{}.ogg",
+        meta.title
+            .unwrap_or_else(|| "track".to_owned())
+            .replace(|c: char| !c.is_alphanumeric() && c != ' ' && c != '-', "_")
+    );
+    handle_message(
+        msg.channel_id
+            .send_files(&ctx, vec![(ogg.as_slice(), name.as_str())], |m| m)
+            .await,
+    );
+
+    Ok(())
+}
@@ -0,0 +1,167 @@
+use super::utils::*;
+#[cfg(feature = "cache")]
+use crate::library::{Library, LibraryConfig, Track};
+#[cfg(feature = "cache")]
+use serenity::framework::standard::Delimiter;
+use serenity::{
+    client::Context,
+    framework::standard::{macros::command, Args, CommandResult},
+    model::channel::Message,
+};
+#[cfg(feature = "cache")]
+use tracing::warn;
+
+/// Results shown by `lib search`, as many as `pick` can show
+#[cfg(feature = "cache")]
+const RESULTS: usize = 16;
+
+#[command]
+#[aliases("library", "local")]
+#[only_in(guilds)]
+#[description = "Search the music library on the bot's host and add from it"]
+#[usage = "[search <terms> | add <id or terms> | scan]"]
+pub async fn lib(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    manage(ctx, msg, args).await
+}
+
+#[cfg(not(feature = "cache"))]
+async fn manage(ctx: &Context, msg: &Message, _: Args) -> CommandResult {
+    handle_message(
+        msg.channel_id
+            .say(
+                &ctx,
+                "The music library needs the database, which is disabled",
+            )
+            .await,
+    );
+    Ok(())
+}
+
+#[cfg(feature = "cache")]
+async fn manage(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let (library, config) = {
+        let read = ctx.data.read().await;
+        (
+            read.get::<Library>().cloned(),
+            read.get::<LibraryConfig>().cloned().unwrap_or_default(),
+        )
+    };
+    let library = if let Some(l) = library {
+        l
+    } else {
+        handle_message(
+            msg.channel_id
+                .say(&ctx, "The database is unavailable")
+                .await,
+        );
+        return Ok(());
+    };
+
+    let action = args.single::<String>().unwrap_or_default();
+    let query = args.rest().trim().to_owned();
+
+    match action.as_str() {
+        "search" | "s" if !query.is_empty() => {
+            let results = library.search(&query, RESULTS).await?;
+            let text = if results.is_empty() {
+                format!("No results for {}", query)
+            } else {
+                results
+                    .iter()
+                    .map(|t| format!("`{}`: {}", t.id, describe(t)))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            };
+            let colour = cached_colour(ctx, msg.guild(&ctx.cache).await).await;
+
+            handle_message(
+                msg.channel_id
+                    .send_message(&ctx, |m| {
+                        m.embed(|e| {
+                            e.title(format!("Library results for {}", query))
+                                .description(text)
+                                .footer(|f| f.text("Add one with `lib add <id>`"))
+                                .colour(colour)
+                        })
+                    })
+                    .await,
+            );
+        }
+        "add" | "a" if !query.is_empty() => {
+            let track = match query.parse::<i64>() {
+                Ok(id) => library.get(id).await?,
+                Err(_) => {
+                    let mut results = library.search(&query, RESULTS).await?;
+                    if results.len() > 1 {
+                        let options = results.iter().map(describe).collect::<Vec<String>>();
+                        let title = format!("Library results for {}", query);
+                        pick(ctx, msg, &title, &options)
+                            .await
+                            .map(|i| results.swap_remove(i))
+                    } else {
+                        results.pop()
+                    }
+                }
+            };
+            match track {
+                Some(t) => {
+                    let query = format!("lib:{}", t.id);
+                    super::add::add(ctx, msg, Args::new(&query, &[Delimiter::Single(' ')])).await?
+                }
+                None => handle_message(
+                    msg.channel_id
+                        .say(&ctx, format!("Nothing in the library for {}", query))
+                        .await,
+                ),
+            }
+        }
+        "scan" => {
+            if let Some(m) = &msg.member {
+                if !permission_check(ctx, m).await {
+                    return Ok(());
+                }
+            } else {
+                return Ok(());
+            }
+            handle_message(msg.channel_id.say(&ctx, "Scanning the library").await);
+            let text = match library.scan(&config).await {
+                Ok(Some(s)) => format!(
+                    "Library scanned: {} added, {} updated, {} removed",
+                    s.added, s.updated, s.removed
+                ),
+                Ok(None) => "The library is already being scanned".to_owned(),
+                Err(e) => {
+                    warn!("Error scanning the music library: {}", e);
+                    "Couldn't scan the library".to_owned()
+                }
+            };
+            handle_message(msg.channel_id.say(&ctx, text).await);
+        }
+        _ => handle_message(
+            msg.channel_id
+                .say(
+                    &ctx,
+                    "Usage: lib [search <terms> | add <id or terms> | scan]",
+                )
+                .await,
+        ),
+    }
+
+    Ok(())
+}
+
+/// Artist, title, album and length, whichever are known
+#[cfg(feature = "cache")]
+fn describe(track: &Track) -> String {
+    let mut text = match &track.artist {
+        Some(a) => format!("{} - {}", a, track.title()),
+        None => track.title(),
+    };
+    if let Some(a) = &track.album {
+        text += &format!(" ({})", a);
+    }
+    if let Some(d) = track.duration {
+        text += &format!(" {}:{:02}", d.as_secs() / 60, d.as_secs() % 60);
+    }
+    text
+}
@@ -1,4 +1,5 @@
-use super::{CommandCounter, ShardManagerContainer};
+use super::{CommandCounter, SavedQueues, ShardManagerContainer};
+use crate::attachment::{self, AttachmentConfig};
 use serenity::{
     client::Context,
     framework::standard::{macros::command, CommandResult},
@@ -9,24 +10,66 @@ use utils::*;
 
 pub mod add;
 pub mod display;
+pub mod export;
 pub mod hooks;
+pub mod library;
+pub mod preset;
 pub mod queue;
+pub mod radio;
+pub mod rebroadcast;
+pub mod record;
+pub mod resolve;
+pub mod secret;
 pub mod utils;
 
 pub use add::*;
 pub use display::*;
+pub use export::*;
 pub use hooks::*;
+pub use library::*;
+pub use preset::*;
 pub use queue::*;
+pub use radio::*;
+pub use rebroadcast::*;
+pub use record::*;
+pub use secret::*;
 
 struct TrackOwner;
 
 impl TypeMapKey for TrackOwner {
     type Value = UserId;
 }
+
+struct TrackAudio;
+
+impl TypeMapKey for TrackAudio {
+    type Value = crate::export::OpusSource;
+}
+
+struct TrackLive;
+
+impl TypeMapKey for TrackLive {
+    type Value = crate::icy::SharedLive;
+}
+
+/// What's needed to queue the track again after `leave`
+struct TrackEntry;
+
+impl TypeMapKey for TrackEntry {
+    type Value = crate::QueueEntry;
+}
+
+/// Set on queued tracks that haven't been opened yet
+struct TrackPrefetch;
+
+impl TypeMapKey for TrackPrefetch {
+    type Value = crate::lazy::Prefetch;
+}
+
 #[command]
 #[aliases("l")]
 #[only_in(guilds)]
-#[description = "Leave the voice channel, flushing the queue"]
+#[description = "Leave the voice channel, flushing the queue. `restore` queues it again"]
 // TODO: Check for user in channel
 pub async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
     if let Some(m) = &msg.member {
@@ -44,7 +87,19 @@ pub async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
     let manager = songbird::get(ctx).await.unwrap().clone();
 
     if let Some(lock) = manager.get(guild_id) {
-        let _ = lock.lock().await.queue().stop();
+        let queue = lock.lock().await.queue().clone();
+        let mut saved = Vec::new();
+        for track in queue.current_queue() {
+            if let Some(e) = track.typemap().read().await.get::<TrackEntry>() {
+                saved.push(e.clone());
+            }
+        }
+        if !saved.is_empty() {
+            let mut write = ctx.data.write().await;
+            write.get_mut::<SavedQueues>().unwrap().insert(guild_id, saved);
+        }
+
+        let _ = queue.stop();
         if let Err(e) = manager.remove(guild_id).await {
             handle_message(
                 msg.channel_id
@@ -54,6 +109,13 @@ pub async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
         }
     }
 
+    // The downloads of the tracks that were still queued
+    let attachments = {
+        let read = ctx.data.read().await;
+        read.get::<AttachmentConfig>().cloned().unwrap_or_default()
+    };
+    attachment::clear(&attachments.guild_dir(guild_id)).await;
+
     Ok(())
 }
 
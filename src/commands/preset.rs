@@ -0,0 +1,146 @@
+use super::utils::*;
+#[cfg(feature = "cache")]
+use crate::{
+    auth,
+    presets::{Kind, Preset, Presets},
+};
+use serenity::{
+    client::Context,
+    framework::standard::{macros::command, Args, CommandResult},
+    model::channel::Message,
+};
+#[cfg(feature = "cache")]
+use tracing::warn;
+
+#[command]
+#[aliases("presets", "station")]
+#[only_in(guilds)]
+#[description = "Save stations to add with `icecast <name>` or `raw <name>`, \
+which play them the way their type says"]
+#[usage = "[list | add <name> <url> [icecast | raw] | remove <name>]"]
+pub async fn preset(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    manage(ctx, msg, args).await
+}
+
+#[cfg(not(feature = "cache"))]
+async fn manage(ctx: &Context, msg: &Message, _: Args) -> CommandResult {
+    handle_message(
+        msg.channel_id
+            .say(&ctx, "Presets need the database, which is disabled")
+            .await,
+    );
+    Ok(())
+}
+
+#[cfg(feature = "cache")]
+async fn manage(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let presets = {
+        let read = ctx.data.read().await;
+        read.get::<Presets>().cloned()
+    };
+    let presets = if let Some(p) = presets {
+        p
+    } else {
+        handle_message(
+            msg.channel_id
+                .say(&ctx, "The database is unavailable")
+                .await,
+        );
+        return Ok(());
+    };
+
+    let action = args
+        .single::<String>()
+        .unwrap_or_else(|_| "list".to_owned());
+    if action != "list" && action != "ls" {
+        if let Some(m) = &msg.member {
+            if !permission_check(ctx, m).await {
+                return Ok(());
+            }
+        } else {
+            return Ok(());
+        }
+    }
+
+    let text = match action.as_str() {
+        "list" | "ls" => {
+            let list = presets.list(guild_id).await?;
+            let text = if list.is_empty() {
+                "No presets".to_owned()
+            } else {
+                list.iter()
+                    // Credentials in the link stay out of the embed
+                    .map(|p| format!("`{}`: <{}> ({})", p.name, auth::split(&p.url).0, p.kind))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            };
+            let colour = cached_colour(ctx, msg.guild(&ctx.cache).await).await;
+
+            handle_message(
+                msg.channel_id
+                    .send_message(&ctx, |m| {
+                        m.embed(|e| e.title("Presets").description(text).colour(colour))
+                    })
+                    .await,
+            );
+            return Ok(());
+        }
+        "add" | "save" => {
+            let (name, url) = match (args.single::<String>(), args.single::<String>()) {
+                (Ok(n), Ok(u)) => (n, u),
+                _ => {
+                    handle_message(
+                        msg.channel_id
+                            .say(&ctx, "Usage: preset add <name> <url> [icecast | raw]")
+                            .await,
+                    );
+                    return Ok(());
+                }
+            };
+            let kind = match args.single::<String>() {
+                Ok(k) => match k.parse::<Kind>() {
+                    Ok(k) => k,
+                    Err(_) => {
+                        handle_message(
+                            msg.channel_id
+                                .say(&ctx, "The type can be icecast or raw")
+                                .await,
+                        );
+                        return Ok(());
+                    }
+                },
+                Err(_) => Kind::Icecast,
+            };
+            // Otherwise it would shadow the URL
+            if name.contains("://") {
+                handle_message(msg.channel_id.say(&ctx, "That's not a name").await);
+                return Ok(());
+            }
+
+            let preset = Preset { name, url, kind };
+            match presets.insert(guild_id, &preset).await {
+                Ok(()) => format!("Saved {}", preset.name.to_lowercase()),
+                Err(e) => {
+                    warn!("Error saving preset: {}", e);
+                    format!("Error: {}", e)
+                }
+            }
+        }
+        "remove" | "rm" => {
+            let name = args.single::<String>().unwrap_or_default();
+            match presets.remove(guild_id, &name).await {
+                Ok(true) => format!("Removed {}", name.to_lowercase()),
+                Ok(false) => format!("No preset {}", name),
+                Err(e) => {
+                    warn!("Error removing preset: {}", e);
+                    format!("Error: {}", e)
+                }
+            }
+        }
+        _ => "Usage: preset [list | add <name> <url> [icecast | raw] | remove <name>]".to_owned(),
+    };
+    handle_message(msg.channel_id.say(&ctx, text).await);
+
+    Ok(())
+}
@@ -142,9 +142,13 @@ pub async fn pop(ctx: &Context, msg: &Message) -> CommandResult {
                 .await
                 .unwrap_or(msg.author.name.clone());
             let meta = removed.metadata().clone();
-            let url = meta.source_url.unwrap();
-            let title = meta.title.unwrap_or(url.clone());
-            let desc = format!("`{}`: [{}]({})\nRequested by {}", i, title, url, nick);
+            let url = meta.source_url.as_deref();
+            let title = meta
+                .title
+                .clone()
+                .or_else(|| source_link(url).map(str::to_owned))
+                .unwrap_or("?".to_owned());
+            let desc = format!("`{}`: {}\nRequested by {}", i, linked_title(title, url), nick);
             let colour = cached_colour(ctx, msg.guild(&ctx.cache).await).await;
 
             handle_message(
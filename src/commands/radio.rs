@@ -0,0 +1,211 @@
+use super::{add, utils::*, TrackLive};
+use crate::{
+    auth::Credentials,
+    directory::{self, Directory, DirectoryConfig},
+    icecast::IcecastConfig,
+    icy::SharedLive,
+    station::{self, Server},
+};
+use serenity::{
+    client::Context,
+    framework::standard::{macros::command, Args, CommandResult, Delimiter},
+    model::{channel::Message, id::ChannelId},
+};
+use songbird::tracks::{PlayMode, TrackHandle};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Re-fetches the status of a radio stream while its track is in the queue,
+/// announcing song changes in `channel` if enabled
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_poller(
+    ctx: &Context,
+    channel: ChannelId,
+    handle: TrackHandle,
+    live: SharedLive,
+    server: Server,
+    query: String,
+    auth: Option<Credentials>,
+    config: IcecastConfig,
+) {
+    if config.poll_interval == 0 {
+        return;
+    }
+    let http = ctx.http.clone();
+
+    tokio::spawn(async move {
+        let mut announced = live.read().await.display_title();
+        let mut interval = tokio::time::interval(Duration::from_secs(config.poll_interval));
+
+        loop {
+            interval.tick().await;
+            // Errors out once the track is gone
+            match handle.get_info().await {
+                Ok(s) if s.playing == PlayMode::Play => (),
+                Ok(s) if s.playing.is_done() => break,
+                Ok(_) => continue,
+                Err(_) => break,
+            }
+
+            match station::fetch(server, &query, config.admin(&query), auth.as_ref()).await {
+                Ok(status) => {
+                    let mut write = live.write().await;
+                    write.set_song(status.title, status.artist);
+                    write.station.merge(status.station);
+                }
+                Err(e) => debug!("Error refreshing status of {}: {}", query, e),
+            }
+
+            let current = live.read().await.display_title();
+            if current.is_some() && current != announced {
+                if config.announce {
+                    handle_message(
+                        channel
+                            .say(&http, format!("Now on air: {}", current.as_ref().unwrap()))
+                            .await,
+                    );
+                }
+                announced = current;
+            }
+        }
+        debug!("Stopped polling {}", query);
+    });
+}
+
+#[command]
+#[aliases("h", "recent")]
+#[only_in(guilds)]
+#[description = "List the last songs played on a stream, defaults to the current one"]
+#[usage = "[queue index]"]
+pub async fn history(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let index = args.single::<usize>().unwrap_or(0);
+    let manager = songbird::get(ctx).await.unwrap().clone();
+
+    let handle = if let Some(lock) = manager.get(msg.guild_id.unwrap()) {
+        let call = lock.lock().await;
+        call.queue().current_queue().get(index).cloned()
+    } else {
+        handle_message(msg.channel_id.say(&ctx, "Not in a voice channel").await);
+        return Ok(());
+    };
+
+    let live = if let Some(h) = &handle {
+        let read = h.typemap().read().await;
+        read.get::<TrackLive>().cloned()
+    } else {
+        None
+    };
+    let (history, station) = if let Some(l) = live {
+        let read = l.read().await;
+        (read.history.clone(), read.station.name.clone())
+    } else {
+        handle_message(
+            msg.channel_id
+                .say(&ctx, format!("Queue entry {} is not a stream", index))
+                .await,
+        );
+        return Ok(());
+    };
+
+    let text = if history.is_empty() {
+        "Nothing yet".to_owned()
+    } else {
+        history
+            .iter()
+            .enumerate()
+            .map(|(i, t)| format!("`{}`: {}", i, t))
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+    let title = station
+        .or_else(|| handle.and_then(|h| h.metadata().title.clone()))
+        .unwrap_or_else(|| "stream".to_owned());
+    let colour = cached_colour(ctx, msg.guild(&ctx.cache).await).await;
+
+    handle_message(
+        msg.channel_id
+            .send_message(&ctx, |m| {
+                m.embed(|e| {
+                    e.title(format!("Recently on {}", title))
+                        .description(text)
+                        .colour(colour)
+                })
+            })
+            .await,
+    );
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[description = "Search the Icecast directory for a station to add"]
+#[usage = "search <terms>"]
+pub async fn radio(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let action = args.single::<String>().unwrap_or_default();
+    let query = args.rest().trim().to_owned();
+    if (action != "search" && action != "s") || query.is_empty() {
+        handle_message(
+            msg.channel_id
+                .say(&ctx, "Usage: radio search <terms>")
+                .await,
+        );
+        return Ok(());
+    }
+
+    let (config, directory) = {
+        let read = ctx.data.read().await;
+        (
+            read.get::<DirectoryConfig>().cloned().unwrap_or_default(),
+            read.get::<Directory>().cloned().unwrap(),
+        )
+    };
+    let stations = match directory.stations(&config).await {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Error reading the directory at {}: {}", config.url, e);
+            handle_message(
+                msg.channel_id
+                    .say(&ctx, "Couldn't read the station directory")
+                    .await,
+            );
+            return Ok(());
+        }
+    };
+
+    let results = directory::search(&stations, &query);
+    if results.is_empty() {
+        handle_message(
+            msg.channel_id
+                .say(&ctx, format!("No stations found for {}", query))
+                .await,
+        );
+        return Ok(());
+    }
+    let options = results
+        .iter()
+        .map(|s| {
+            let mut details = Vec::new();
+            if let Some(g) = &s.genre {
+                details.push(g.clone());
+            }
+            if let Some(b) = s.bitrate {
+                details.push(format!("{}kbps", b));
+            }
+            if let Some(c) = &s.current_song {
+                details.push(format!("playing {}", c));
+            }
+            format!("[{}]({}) {}", s.name, s.url, details.join(", "))
+        })
+        .collect::<Vec<String>>();
+
+    match pick(ctx, msg, &format!("Stations matching {}", query), &options).await {
+        Some(i) => {
+            let args = Args::new(&results[i].url, &[Delimiter::Single(' ')]);
+            add::icecast(ctx, msg, args).await?;
+        }
+        None => handle_message(msg.channel_id.say(&ctx, "Nothing picked").await),
+    }
+
+    Ok(())
+}
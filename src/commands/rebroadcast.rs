@@ -0,0 +1,109 @@
+use super::utils::*;
+use crate::{
+    rebroadcast::{RebroadcastConfig, Rebroadcasts},
+    server::ServerConfig,
+    tap::Taps,
+};
+use serenity::{
+    client::Context,
+    framework::standard::{macros::command, Args, CommandResult},
+    model::channel::Message,
+};
+
+#[command]
+#[aliases("relay", "rb")]
+#[only_in(guilds)]
+#[description = "Stream what's playing to the configured Icecast server"]
+#[usage = "[on | off]"]
+pub async fn rebroadcast(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let (config, rebroadcasts, taps) = {
+        let read = ctx.data.read().await;
+        (
+            read.get::<RebroadcastConfig>().cloned().unwrap_or_default(),
+            read.get::<Rebroadcasts>().cloned().unwrap(),
+            read.get::<Taps>().cloned().unwrap(),
+        )
+    };
+    let url = if let Some(u) = config.listen_url(guild_id) {
+        u
+    } else {
+        handle_message(
+            msg.channel_id
+                .say(&ctx, "No Icecast server configured")
+                .await,
+        );
+        return Ok(());
+    };
+
+    let action = args.single::<String>().ok();
+    if action.is_some() {
+        if let Some(m) = &msg.member {
+            if !permission_check(ctx, m).await {
+                return Ok(());
+            }
+        } else {
+            return Ok(());
+        }
+    }
+
+    let text = match action.as_deref() {
+        Some("on") | Some("start") => {
+            let name = match msg.guild(&ctx.cache).await {
+                Some(g) => format!("{} on sodmb", g.name),
+                None => "sodmb".to_owned(),
+            };
+            let manager = songbird::get(ctx).await.unwrap().clone();
+            rebroadcasts.start(guild_id, name, taps.subscribe(guild_id), config, manager);
+            format!("Rebroadcasting to <{}>", url)
+        }
+        Some("off") | Some("stop") => {
+            if rebroadcasts.stop(guild_id) {
+                "Stopped rebroadcasting".to_owned()
+            } else {
+                "Not rebroadcasting".to_owned()
+            }
+        }
+        Some(_) => "Usage: rebroadcast [on | off]".to_owned(),
+        None if rebroadcasts.active(guild_id) => format!("Rebroadcasting to <{}>", url),
+        None => "Not rebroadcasting".to_owned(),
+    };
+    handle_message(msg.channel_id.say(&ctx, text).await);
+
+    Ok(())
+}
+
+#[command]
+#[aliases("url", "link")]
+#[only_in(guilds)]
+#[description = "Get a link to listen to the bot outside of Discord"]
+pub async fn listen(ctx: &Context, msg: &Message) -> CommandResult {
+    let config = {
+        let read = ctx.data.read().await;
+        read.get::<ServerConfig>().cloned().unwrap_or_default()
+    };
+    let url = if let Some(u) = config.stream_url(msg.guild_id.unwrap()) {
+        u
+    } else {
+        handle_message(
+            msg.channel_id
+                .say(&ctx, "The stream server is disabled")
+                .await,
+        );
+        return Ok(());
+    };
+
+    // Links with the token are only sent privately
+    if config.token.is_some() {
+        handle_message(
+            msg.author
+                .direct_message(&ctx, |m| m.content(format!("<{}>", url)))
+                .await,
+        );
+        handle_message(msg.reply(&ctx, "Sent you the link").await);
+    } else {
+        handle_message(msg.channel_id.say(&ctx, format!("<{}>", url)).await);
+    }
+
+    Ok(())
+}
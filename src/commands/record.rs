@@ -0,0 +1,229 @@
+use super::utils::*;
+use crate::{
+    date,
+    export::{self, UPLOAD_LIMIT},
+    record::{self, RecordConfig, Recordings},
+    tap::Taps,
+};
+use chrono::Utc;
+use serenity::{
+    client::Context,
+    framework::standard::{macros::command, Args, CommandResult},
+    model::channel::Message,
+};
+use std::time::Duration;
+use tracing::warn;
+
+#[command]
+#[aliases("rec")]
+#[only_in(guilds)]
+#[description = "Record what's playing to a file, list the recordings or upload one"]
+#[usage = "[start [minutes] | stop | list | get <index or name>]"]
+pub async fn record(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let action = args
+        .single::<String>()
+        .unwrap_or_else(|_| "start".to_owned());
+    let (config, recordings, taps) = {
+        let read = ctx.data.read().await;
+        (
+            read.get::<RecordConfig>().cloned().unwrap_or_default(),
+            read.get::<Recordings>().cloned().unwrap(),
+            read.get::<Taps>().cloned().unwrap(),
+        )
+    };
+
+    match action.as_str() {
+        "start" => {
+            if let Some(m) = &msg.member {
+                if !permission_check(ctx, m).await {
+                    return Ok(());
+                }
+            } else {
+                return Ok(());
+            }
+            if let Some((path, started)) = recordings.active(guild_id) {
+                handle_message(
+                    msg.channel_id
+                        .say(
+                            &ctx,
+                            format!(
+                                "Already recording to {} for {}",
+                                record::file_name(&path),
+                                date::format_duration(Utc::now() - started)
+                            ),
+                        )
+                        .await,
+                );
+                return Ok(());
+            }
+
+            let manager = songbird::get(ctx).await.unwrap().clone();
+            let current = if let Some(lock) = manager.get(guild_id) {
+                let call = lock.lock().await;
+                call.queue().current()
+            } else {
+                handle_message(msg.channel_id.say(&ctx, "Not in a voice channel").await);
+                return Ok(());
+            };
+            let meta = match &current {
+                Some(h) => track_metadata(h).await,
+                None => Default::default(),
+            };
+
+            let max = Duration::from_secs(config.max_duration);
+            let duration = args
+                .single::<u64>()
+                .map(|m| Duration::from_secs(m * 60).min(max))
+                .unwrap_or(max);
+
+            let http = ctx.http.clone();
+            let channel = msg.channel_id;
+            let on_end = move |text: String| {
+                tokio::spawn(async move { handle_message(channel.say(&http, text).await) });
+            };
+
+            match recordings
+                .start(
+                    guild_id,
+                    taps.subscribe(guild_id),
+                    &config,
+                    duration,
+                    meta,
+                    on_end,
+                )
+                .await
+            {
+                Ok(path) => handle_message(
+                    msg.channel_id
+                        .say(
+                            &ctx,
+                            format!(
+                                "Recording to {} for up to {}",
+                                record::file_name(&path),
+                                date::format_duration(chrono::Duration::from_std(duration)?)
+                            ),
+                        )
+                        .await,
+                ),
+                Err(e) => {
+                    warn!("Error starting recording: {}", e);
+                    handle_message(msg.channel_id.say(&ctx, format!("Error: {}", e)).await);
+                }
+            }
+        }
+        "stop" => {
+            if let Some(m) = &msg.member {
+                if !permission_check(ctx, m).await {
+                    return Ok(());
+                }
+            } else {
+                return Ok(());
+            }
+            let text = match recordings.stop(guild_id) {
+                Some(p) => format!("Saved {}", record::file_name(&p)),
+                None => "Not recording".to_owned(),
+            };
+            handle_message(msg.channel_id.say(&ctx, text).await);
+        }
+        "list" | "ls" => {
+            let files = config.list(guild_id)?;
+            let text = if files.is_empty() {
+                "No recordings".to_owned()
+            } else {
+                files
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (name, size))| format!("`{}`: {} ({}KiB)", i, name, size / 1024))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            };
+            let colour = cached_colour(ctx, msg.guild(&ctx.cache).await).await;
+
+            handle_message(
+                msg.channel_id
+                    .send_message(&ctx, |m| {
+                        m.embed(|e| e.title("Recordings").description(text).colour(colour))
+                    })
+                    .await,
+            );
+        }
+        "get" | "dl" => {
+            let which = args.single::<String>().unwrap_or_else(|_| "0".to_owned());
+            // Either an index in `record list` or a file name
+            let name = match which.parse::<usize>() {
+                Ok(i) => config.list(guild_id)?.into_iter().nth(i).map(|(n, _)| n),
+                Err(_) => Some(which.clone()),
+            };
+            let path = if let Some(p) = name.and_then(|n| config.path(guild_id, &n)) {
+                p
+            } else {
+                handle_message(
+                    msg.channel_id
+                        .say(&ctx, format!("No recording {}", which))
+                        .await,
+                );
+                return Ok(());
+            };
+            if recordings
+                .active(guild_id)
+                .map_or(false, |(p, _)| p == path)
+            {
+                handle_message(
+                    msg.channel_id
+                        .say(&ctx, "Still recording, stop it first")
+                        .await,
+                );
+                return Ok(());
+            }
+
+            let ogg = {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || export::dca_file_to_ogg(&path, Vec::new()))
+                    .await?
+            };
+            let ogg = match ogg {
+                Ok(o) => o,
+                Err(e) => {
+                    warn!("Error exporting recording: {}", e);
+                    handle_message(msg.channel_id.say(&ctx, format!("Error: {}", e)).await);
+                    return Ok(());
+                }
+            };
+            if ogg.len() > UPLOAD_LIMIT {
+                handle_message(
+                    msg.channel_id
+                        .say(
+                            &ctx,
+                            format!(
+                                "The file is {}MiB, over Discord's {}MiB limit",
+                                ogg.len() / 1024 / 1024,
+                                UPLOAD_LIMIT / 1024 / 1024
+                            ),
+                        )
+                        .await,
+                );
+                return Ok(());
+            }
+
+            let name = record::file_name(&path).replace(".dca", ".ogg");
+            handle_message(
+                msg.channel_id
+                    .send_files(&ctx, vec![(ogg.as_slice(), name.as_str())], |m| m)
+                    .await,
+            );
+        }
+        _ => {
+            handle_message(
+                msg.channel_id
+                    .say(
+                        &ctx,
+                        "Usage: record [start [minutes] | stop | list | get <index>]",
+                    )
+                    .await,
+            );
+        }
+    }
+
+    Ok(())
+}
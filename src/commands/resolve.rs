@@ -0,0 +1,628 @@
+use super::{radio, utils::*};
+use crate::{
+    auth::{self, Credentials},
+    icy::{self, Reconnect, SharedLive, StreamConfig},
+    playlist::{self, Entry, Format, PROTOCOLS},
+    sniff::{self, Sniffed},
+    ytdl::{self, Listing, PlaylistConfig},
+};
+use futures::future::{BoxFuture, FutureExt};
+use serenity::{async_trait, client::Context, model::channel::Message};
+use songbird::{
+    input::{Input, Metadata},
+    tracks::TrackHandle,
+};
+use std::{io, sync::Arc, time::Duration};
+use tracing::info;
+
+#[cfg(feature = "cache")]
+use crate::{library::Library, secrets::Secrets, server::ServerConfig};
+#[cfg(feature = "cache")]
+use tracing::warn;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Opens the input of a `Lazy` track
+pub type Pending = BoxFuture<'static, Result<Track, Error>>;
+
+/// Sites only ytdl can play, not worth sniffing
+const YTDL_SITES: &[&str] = &[
+    "youtube.com/",
+    "youtu.be/",
+    "soundcloud.com/",
+    "bandcamp.com/",
+];
+/// Links to plain audio files, played with ffmpeg rather than ytdl
+const AUDIO_EXTENSIONS: &[&str] = &["aac", "flac", "m4a", "mp3", "oga", "ogg", "opus", "wav"];
+
+/// An input ready to be queued
+pub struct Track {
+    pub input: Input,
+    /// In-band metadata of a live stream
+    pub live: Option<SharedLive>,
+    /// Read into memory, and cached, when short enough. Off for inputs that are cheap
+    /// to read.
+    pub compress: bool,
+    /// Run with the handle once the track is queued
+    pub queued: Option<Box<dyn FnOnce(TrackHandle) + Send>>,
+}
+
+impl Track {
+    pub fn new(input: Input) -> Self {
+        Track {
+            input,
+            live: None,
+            compress: true,
+            queued: None,
+        }
+    }
+}
+
+/// A track holding only what's known about it until it's about to play, so nothing is
+/// opened when it's added
+pub struct Lazy {
+    pub meta: Metadata,
+    pub open: Pending,
+}
+
+pub enum Resolved {
+    Track(Lazy),
+    Playlist {
+        title: Option<String>,
+        entries: Vec<Lazy>,
+    },
+}
+
+/// A kind of source, turning the queries it handles into inputs
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    /// Used to pick the resolver by hand, like `raw` does
+    fn name(&self) -> &'static str;
+    /// Whether `add` should route the query here. Links nothing but ytdl takes by
+    /// their looks are asked again with what the server `sniffed` they serve.
+    fn handles(&self, query: &str, sniffed: Option<&Sniffed>) -> bool;
+    async fn resolve(&self, ctx: &Context, msg: &Message, query: &str) -> Result<Resolved, Error>;
+}
+
+/// Known resolvers, in routing order
+pub static RESOLVERS: &[&'static dyn Resolver] = &[
+    #[cfg(feature = "cache")]
+    &LibraryResolver,
+    &PlaylistResolver,
+    &IcecastResolver,
+    &FfmpegResolver,
+    // Takes anything else, as a link or search terms
+    &YtdlResolver,
+];
+
+/// The first resolver handling the query. Links only ytdl would take get sniffed, in
+/// case they're audio files or streams without a telling URL.
+pub async fn find(ctx: &Context, msg: &Message, query: &str) -> Option<&'static dyn Resolver> {
+    let first = |sniffed: Option<&Sniffed>| {
+        RESOLVERS
+            .iter()
+            .find(|r| r.handles(query, sniffed))
+            .copied()
+    };
+    let by_query = first(None);
+    if !query.starts_with("http")
+        || by_query.map_or(false, |r| r.name() != YtdlResolver.name())
+        || YTDL_SITES.iter().any(|s| query.contains(s))
+    {
+        return by_query;
+    }
+
+    let (url, given) = auth::split(query);
+    let auth = credentials(ctx, msg, &url, &given).await;
+    match sniff::sniff(&url, auth.as_ref()).await {
+        Some(s) => first(Some(&s)),
+        None => by_query,
+    }
+}
+
+pub fn get(name: &str) -> Option<&'static dyn Resolver> {
+    RESOLVERS.iter().find(|r| r.name() == name).copied()
+}
+
+/// songbird's errors only implement `Debug`
+fn input_error(e: songbird::input::error::Error) -> Error {
+    format!("{:?}", e).into()
+}
+
+/// Last path segment of a link
+fn file_name(url: &str) -> Option<String> {
+    let path = url.split(|c| c == '?' || c == '#').next()?;
+    let name = path.trim_end_matches('/').rsplit('/').next()?;
+    Some(name.to_owned()).filter(|n| !n.is_empty() && !n.contains("://"))
+}
+
+/// Extension of the last path segment of a link
+fn extension(url: &str) -> Option<String> {
+    let name = file_name(url)?;
+    let (_, ext) = name.split_at(name.rfind('.')? + 1);
+    Some(ext.to_ascii_lowercase())
+}
+
+/// YouTube and anything else youtube-dl supports, and search terms
+struct YtdlResolver;
+
+#[async_trait]
+impl Resolver for YtdlResolver {
+    fn name(&self) -> &'static str {
+        "ytdl"
+    }
+
+    fn handles(&self, _: &str, _: Option<&Sniffed>) -> bool {
+        true
+    }
+
+    async fn resolve(&self, ctx: &Context, _: &Message, query: &str) -> Result<Resolved, Error> {
+        // The first result's metadata is all the queue needs until it plays
+        if !query.starts_with("http") {
+            return match ytdl::search(query, 1).await?.pop() {
+                Some(meta) => Ok(Resolved::Track(ytdl_entry(meta))),
+                None => Err("no results".into()),
+            };
+        }
+        // They'd end up on youtube-dl's command line, and in the logs
+        if auth::split(query).1.is_some() {
+            return Err("youtube-dl can't be given credentials in the link".into());
+        }
+
+        let max = {
+            let read = ctx.data.read().await;
+            read.get::<PlaylistConfig>()
+                .cloned()
+                .unwrap_or_default()
+                .max_entries
+        };
+        match ytdl::list(query, max).await? {
+            Listing::Playlist(playlist) => Ok(Resolved::Playlist {
+                title: playlist.title,
+                entries: playlist.entries.into_iter().map(ytdl_entry).collect(),
+            }),
+            Listing::Video(meta) => Ok(Resolved::Track(ytdl_entry(meta))),
+        }
+    }
+}
+
+/// Opens the video at the metadata's `source_url` with ytdl
+pub fn ytdl_entry(meta: Metadata) -> Lazy {
+    let url = meta.source_url.clone().unwrap_or_default();
+    let open = async move {
+        let input = songbird::ytdl(&url).await.map_err(input_error)?;
+        Ok::<_, Error>(Track::new(input))
+    };
+    Lazy {
+        meta,
+        open: open.boxed(),
+    }
+}
+
+/// Any URI ffmpeg can open, with in-band metadata from HTTP streams.
+/// Only takes other protocols and links to audio files when routing.
+struct FfmpegResolver;
+
+#[async_trait]
+impl Resolver for FfmpegResolver {
+    fn name(&self) -> &'static str {
+        "ffmpeg"
+    }
+
+    fn handles(&self, query: &str, sniffed: Option<&Sniffed>) -> bool {
+        if let Some(s) = sniffed {
+            s.is_audio() || s.is_playlist()
+        } else if query.starts_with("http") {
+            extension(query).map_or(false, |e| AUDIO_EXTENSIONS.contains(&e.as_str()))
+        } else {
+            // Search terms can start like a protocol too
+            query.contains("://") && PROTOCOLS.iter().any(|p| query.starts_with(p))
+        }
+    }
+
+    async fn resolve(&self, ctx: &Context, msg: &Message, query: &str) -> Result<Resolved, Error> {
+        if !PROTOCOLS.iter().any(|p| query.starts_with(p)) {
+            return Err("invalid protocol".into());
+        }
+        let (url, given) = auth::split(query);
+
+        // Playlists don't always have the extension
+        if url.starts_with("http") {
+            match playlist::resolve(&url).await {
+                Ok(Some(entries)) => return Ok(playlist_entries(ctx, msg, entries).await),
+                Ok(None) => (),
+                Err(e) => info!("Couldn't resolve {} as a playlist: {:?}", url, e),
+            }
+        }
+
+        let auth = credentials(ctx, msg, &url, &given).await;
+        let title = file_name(&url);
+        Ok(Resolved::Track(raw_entry(
+            url,
+            auth,
+            title,
+            reconnect(ctx, msg).await,
+        )))
+    }
+}
+
+/// M3U, PLS, XSPF and ASX links, each entry opened with ffmpeg
+struct PlaylistResolver;
+
+#[async_trait]
+impl Resolver for PlaylistResolver {
+    fn name(&self) -> &'static str {
+        "playlist"
+    }
+
+    fn handles(&self, query: &str, _: Option<&Sniffed>) -> bool {
+        query.starts_with("http") && Format::from_url(query).is_some()
+    }
+
+    async fn resolve(&self, ctx: &Context, msg: &Message, query: &str) -> Result<Resolved, Error> {
+        match playlist::resolve(&auth::split(query).0).await? {
+            Some(entries) => Ok(playlist_entries(ctx, msg, entries).await),
+            // HLS playlists look the same, and ffmpeg plays them as streams
+            None => FfmpegResolver.resolve(ctx, msg, query).await,
+        }
+    }
+}
+
+async fn playlist_entries(ctx: &Context, msg: &Message, entries: Vec<Entry>) -> Resolved {
+    let reconnect = reconnect(ctx, msg).await;
+    let entries = entries
+        .into_iter()
+        .map(|entry| {
+            let (url, auth) = auth::split(&entry.url);
+            raw_entry(url, auth, entry.title, reconnect.clone())
+        })
+        .collect();
+    Resolved::Playlist {
+        title: None,
+        entries,
+    }
+}
+
+/// Opens the URI with `open_raw`, titled `title` until then
+fn raw_entry(
+    url: String,
+    auth: Option<Credentials>,
+    title: Option<String>,
+    reconnect: Reconnect,
+) -> Lazy {
+    let meta = Metadata {
+        title: title.clone(),
+        source_url: Some(url.clone()),
+        ..Default::default()
+    };
+    let open = async move {
+        let (mut input, live) = open_raw(&url, auth, reconnect).await.map_err(input_error)?;
+        if input.metadata.title.is_none() {
+            input.metadata.title = title;
+        }
+        Ok::<_, Error>(Track {
+            live,
+            ..Track::new(input)
+        })
+    };
+    Lazy {
+        meta,
+        open: open.boxed(),
+    }
+}
+
+/// Icecast and Shoutcast streams, with the station's status polled while they play.
+/// Takes server links when routing, to pick one of their streams.
+struct IcecastResolver;
+
+#[async_trait]
+impl Resolver for IcecastResolver {
+    fn name(&self) -> &'static str {
+        "icecast"
+    }
+
+    fn handles(&self, query: &str, sniffed: Option<&Sniffed>) -> bool {
+        use crate::icecast;
+
+        if let Some(s) = sniffed {
+            return s.is_station();
+        }
+        query.starts_with("http") && icecast::is_server_url(&auth::split(query).0)
+    }
+
+    async fn resolve(&self, ctx: &Context, msg: &Message, query: &str) -> Result<Resolved, Error> {
+        use crate::{
+            icecast::{self, IcecastConfig},
+            station::{self, SourceStatus},
+        };
+
+        let (mut query, given) = auth::split(query);
+        if !query.starts_with("http") {
+            return Err("invalid protocol".into());
+        }
+        let config = {
+            let read = ctx.data.read().await;
+            read.get::<IcecastConfig>().cloned().unwrap_or_default()
+        };
+
+        // Without a mount, let the user pick one of the server's streams
+        if icecast::is_server_url(&query) {
+            let auth = credentials(ctx, msg, &query, &given).await;
+            let mut mounts = match icecast::list_mounts(&query, auth.as_ref()).await {
+                Ok(m) if !m.is_empty() => m,
+                Ok(_) => return Err("there are no streams on the server".into()),
+                Err(e) => {
+                    info!("Couldn't list the mounts on {}: {:?}", query, e);
+                    return Err("couldn't list the streams on the server".into());
+                }
+            };
+            let options = mounts
+                .iter()
+                .map(|(url, status)| {
+                    let s = &status.station;
+                    let mut details = Vec::new();
+                    if let Some(g) = &s.genre {
+                        details.push(g.clone());
+                    }
+                    if let Some(b) = s.bitrate {
+                        details.push(format!("{}kbps", b));
+                    }
+                    if let Some(l) = s.listeners {
+                        details.push(format!("{} listeners", l));
+                    }
+                    format!(
+                        "[{}]({}) {}",
+                        s.name.as_deref().unwrap_or(url),
+                        url,
+                        details.join(", ")
+                    )
+                })
+                .collect::<Vec<String>>();
+            match pick(ctx, msg, "Streams", &options).await {
+                Some(i) => query = mounts.swap_remove(i).0,
+                None => return Err("nothing picked".into()),
+            }
+        }
+
+        // Station links often point at a playlist of mirrors
+        match playlist::resolve(&query).await {
+            Ok(Some(entries)) => match playlist::first_working(&entries).await {
+                Some(e) => query = e.url.clone(),
+                None => return Err("no working stream in the playlist".into()),
+            },
+            Ok(None) => (),
+            Err(e) => info!("Couldn't resolve {} as a playlist: {:?}", query, e),
+        }
+
+        let auth = credentials(ctx, msg, &query, &given).await;
+        let (server, status) =
+            match station::detect(&query, config.admin(&query), auth.as_ref()).await {
+                Some((server, status)) => (Some(server), status),
+                None => {
+                    info!("No status page found for {}", query);
+                    (None, SourceStatus::minimal(&query))
+                }
+            };
+        let meta = status
+            .clone()
+            .into_metadata(&query, server.and_then(|s| s.thumbnail()));
+        let reconnect = reconnect(ctx, msg).await;
+        let (ctx, channel) = (ctx.clone(), msg.channel_id);
+        let stream_meta = meta.clone();
+        let open = async move {
+            let (input, live) = open_stream(&query, auth.clone(), Some(stream_meta), reconnect)
+                .await
+                .map_err(input_error)?;
+
+            let live = live.unwrap_or_default();
+            {
+                let mut write = live.write().await;
+                write.set_song(status.title, status.artist);
+                write.station.merge(status.station);
+            }
+            let queued = server.map(|s| {
+                let live = live.clone();
+                Box::new(move |handle| {
+                    radio::spawn_poller(&ctx, channel, handle, live, s, query, auth, config)
+                }) as Box<dyn FnOnce(TrackHandle) + Send>
+            });
+
+            Ok::<_, Error>(Track {
+                live: Some(live),
+                queued,
+                ..Track::new(input)
+            })
+        };
+
+        Ok(Resolved::Track(Lazy {
+            meta,
+            open: open.boxed(),
+        }))
+    }
+}
+
+/// `lib:<id>` from the music library, played from disk with the tags read when it
+/// was scanned
+#[cfg(feature = "cache")]
+struct LibraryResolver;
+
+#[cfg(feature = "cache")]
+#[async_trait]
+impl Resolver for LibraryResolver {
+    fn name(&self) -> &'static str {
+        "library"
+    }
+
+    fn handles(&self, query: &str, _: Option<&Sniffed>) -> bool {
+        query.starts_with("lib:")
+    }
+
+    async fn resolve(&self, ctx: &Context, msg: &Message, query: &str) -> Result<Resolved, Error> {
+        let id = query
+            .trim_start_matches("lib:")
+            .parse::<i64>()
+            .map_err(|_| "not a library id")?;
+        let (library, server) = {
+            let read = ctx.data.read().await;
+            (
+                read.get::<Library>().cloned(),
+                read.get::<ServerConfig>().cloned(),
+            )
+        };
+        let track = library
+            .ok_or("the database is unavailable")?
+            .get(id)
+            .await?
+            .ok_or("no such track in the library")?;
+        let thumbnail = match (&track.cover, server) {
+            (Some(c), Some(s)) => s.cover_url(c),
+            _ => None,
+        };
+
+        let meta = Metadata {
+            title: Some(track.title()),
+            artist: track.artist.clone(),
+            duration: track.duration,
+            // Works as a query to add it again, without showing where the file is on the host
+            source_url: Some(format!("lib:{}", id)),
+            thumbnail,
+            ..Default::default()
+        };
+        let reconnect = reconnect(ctx, msg).await;
+        let tags = meta.clone();
+        let open = async move {
+            let (mut input, _) = open_raw(&track.path, None, reconnect)
+                .await
+                .map_err(input_error)?;
+            let meta = &mut input.metadata;
+            meta.title = tags.title;
+            meta.artist = tags.artist.or_else(|| meta.artist.take());
+            meta.duration = tags.duration.or(meta.duration);
+            meta.source_url = tags.source_url;
+            meta.thumbnail = tags.thumbnail;
+
+            // Local files are cheap to read, there's no point in keeping them in memory
+            Ok::<_, Error>(Track {
+                compress: false,
+                ..Track::new(input)
+            })
+        };
+
+        Ok(Resolved::Track(Lazy {
+            meta,
+            open: open.boxed(),
+        }))
+    }
+}
+
+/// Credentials given in the link, or else the ones saved for the stream
+async fn credentials(
+    ctx: &Context,
+    msg: &Message,
+    url: &str,
+    given: &Option<Credentials>,
+) -> Option<Credentials> {
+    if given.is_some() {
+        return given.clone();
+    }
+    #[cfg(feature = "cache")]
+    {
+        let secrets = {
+            let read = ctx.data.read().await;
+            read.get::<Secrets>().cloned()
+        };
+        if let Some(s) = secrets {
+            match s.get(msg.guild_id.unwrap(), url).await {
+                Ok(c) => return c,
+                Err(e) => warn!("Error reading saved credentials: {}", e),
+            }
+        }
+    }
+    #[cfg(not(feature = "cache"))]
+    let _ = (ctx, msg, url);
+    None
+}
+
+/// Reconnection settings for live streams, with the notices posted where `msg` was sent
+pub async fn reconnect(ctx: &Context, msg: &Message) -> Reconnect {
+    let config = {
+        let read = ctx.data.read().await;
+        read.get::<StreamConfig>().cloned().unwrap_or_default()
+    };
+    let http = ctx.http.clone();
+    let channel = msg.channel_id;
+
+    Reconnect {
+        timeout: Duration::from_secs(config.reconnect_timeout),
+        notify: Arc::new(move |text: String| {
+            let http = http.clone();
+            tokio::spawn(async move { handle_message(channel.say(&http, text).await) });
+        }),
+    }
+}
+
+/// Opens any ffmpeg URI, reading in-band metadata from HTTP streams
+pub async fn open_raw(
+    query: &str,
+    auth: Option<Credentials>,
+    reconnect: Reconnect,
+) -> songbird::input::error::Result<(Input, Option<SharedLive>)> {
+    if query.starts_with("http") {
+        return open_stream(query, auth, None, reconnect).await;
+    }
+    // They'd have to go on ffmpeg's command line, where anyone on the machine can read them
+    if auth.is_some() {
+        return Err(songbird::input::error::Error::Io(io::Error::new(
+            io::ErrorKind::Other,
+            "credentials only work with HTTP links",
+        )));
+    }
+    songbird::ffmpeg(query).await.map(|mut i| {
+        if i.metadata.source_url.is_none() {
+            i.metadata.source_url = Some(query.to_owned());
+        }
+        (i, None)
+    })
+}
+
+/// Opens an HTTP stream, reading its in-band metadata and reconnecting when it drops
+/// if it's a radio. Without `meta` the one from ffprobe is kept.
+async fn open_stream(
+    query: &str,
+    auth: Option<Credentials>,
+    meta: Option<Metadata>,
+    reconnect: Reconnect,
+) -> songbird::input::error::Result<(Input, Option<SharedLive>)> {
+    match icy::open(
+        query,
+        auth.clone(),
+        meta.clone().unwrap_or_default(),
+        reconnect,
+    )
+    .await
+    {
+        Ok(Some((input, live))) => return Ok((input, Some(live))),
+        Ok(None) => (),
+        Err(e) => info!("Couldn't read in-band metadata of {}: {:?}", query, e),
+    }
+    // Private links are downloaded by the bot, ffmpeg never sees the credentials
+    if let Some(auth) = &auth {
+        let mut meta = meta.unwrap_or_default();
+        if meta.source_url.is_none() {
+            meta.source_url = Some(query.to_owned());
+        }
+        return icy::open_private(query, auth, meta)
+            .await
+            .map(|i| (i, None));
+    }
+    // Shoutcast v1 answers with "ICY 200 OK", which isn't valid HTTP; ffmpeg copes with it
+    songbird::ffmpeg(query).await.map(|mut i| {
+        if let Some(m) = meta {
+            i.metadata = Box::new(m);
+        }
+        if i.metadata.source_url.is_none() {
+            i.metadata.source_url = Some(query.to_owned());
+        }
+        (i, None)
+    })
+}
@@ -0,0 +1,143 @@
+use super::utils::*;
+#[cfg(feature = "cache")]
+use crate::{
+    auth::{self, Credentials},
+    secrets::Secrets,
+};
+use serenity::{
+    client::Context,
+    framework::standard::{macros::command, Args, CommandResult},
+    model::channel::Message,
+};
+#[cfg(feature = "cache")]
+use tracing::warn;
+
+#[command]
+#[aliases("secrets", "credentials", "login")]
+#[only_in(guilds)]
+#[description = "Save the username and password for a private server or mount"]
+#[usage = "[list | set <server or stream URL> <user> <password> | remove <URL>]"]
+pub async fn secret(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    manage(ctx, msg, args).await
+}
+
+#[cfg(not(feature = "cache"))]
+async fn manage(ctx: &Context, msg: &Message, _: Args) -> CommandResult {
+    handle_message(
+        msg.channel_id
+            .say(
+                &ctx,
+                "Saved credentials need the database, which is disabled",
+            )
+            .await,
+    );
+    Ok(())
+}
+
+#[cfg(feature = "cache")]
+async fn manage(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let action = args
+        .single::<String>()
+        .unwrap_or_else(|_| "list".to_owned());
+    // The password shouldn't stay in the channel
+    if action == "set" || action == "add" {
+        handle_message(msg.delete(&ctx).await);
+    }
+
+    let secrets = {
+        let read = ctx.data.read().await;
+        read.get::<Secrets>().cloned()
+    };
+    let secrets = if let Some(s) = secrets {
+        s
+    } else {
+        handle_message(
+            msg.channel_id
+                .say(&ctx, "The database is unavailable")
+                .await,
+        );
+        return Ok(());
+    };
+
+    if let Some(m) = &msg.member {
+        if !permission_check(ctx, m).await {
+            return Ok(());
+        }
+    } else {
+        return Ok(());
+    }
+
+    // Bare hosts are fine too
+    let target = |url: String| {
+        if url.contains("://") {
+            auth::target(&url)
+        } else {
+            auth::target(&format!("http://{}", url))
+        }
+    };
+
+    let text = match action.as_str() {
+        "list" | "ls" => {
+            let list = secrets.list(guild_id).await?;
+            let text = if list.is_empty() {
+                "No saved credentials".to_owned()
+            } else {
+                list.iter()
+                    .map(|t| format!("`{}`", t))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            };
+            let colour = cached_colour(ctx, msg.guild(&ctx.cache).await).await;
+
+            handle_message(
+                msg.channel_id
+                    .send_message(&ctx, |m| {
+                        m.embed(|e| {
+                            e.title("Saved credentials")
+                                .description(text)
+                                .colour(colour)
+                        })
+                    })
+                    .await,
+            );
+            return Ok(());
+        }
+        "set" | "add" => {
+            match (
+                args.single::<String>().ok().and_then(target),
+                args.single::<String>(),
+                args.single::<String>(),
+            ) {
+                (Some(t), Ok(user), Ok(password)) => {
+                    match secrets
+                        .insert(guild_id, &t, &Credentials { user, password })
+                        .await
+                    {
+                        Ok(()) => format!("Saved credentials for {}", t),
+                        Err(e) => {
+                            warn!("Error saving credentials: {}", e);
+                            "Couldn't save the credentials".to_owned()
+                        }
+                    }
+                }
+                _ => "Usage: secret set <server or stream URL> <user> <password>".to_owned(),
+            }
+        }
+        "remove" | "rm" => match args.single::<String>().ok().and_then(target) {
+            Some(t) => match secrets.remove(guild_id, &t).await {
+                Ok(true) => format!("Removed the credentials for {}", t),
+                Ok(false) => format!("No credentials for {}", t),
+                Err(e) => {
+                    warn!("Error removing credentials: {}", e);
+                    "Couldn't remove the credentials".to_owned()
+                }
+            },
+            None => "Usage: secret remove <URL>".to_owned(),
+        },
+        _ => "Usage: secret [list | set <URL> <user> <password> | remove <URL>]".to_owned(),
+    };
+    handle_message(msg.channel_id.say(&ctx, text).await);
+
+    Ok(())
+}
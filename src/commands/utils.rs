@@ -1,12 +1,17 @@
+use super::TrackLive;
 use serenity::{
     client::Context,
     model::{
+        channel::{Message, ReactionType},
         guild::{Guild, PartialMember},
+        id::GuildId,
         permissions::Permissions,
     },
     utils::Colour,
     Result as SerenityResult,
 };
+use songbird::{input::Metadata, tracks::TrackHandle, Songbird};
+use std::time::Duration;
 use tracing::{warn, info};
 
 pub fn handle_message<T>(res: SerenityResult<T>) {
@@ -47,3 +52,118 @@ pub async fn cached_colour(ctx: &Context, guild: Option<Guild>) -> Colour {
     };
     Colour(0xffffff)
 }
+
+/// Metadata of a queued track, with the live stream title if there is one
+pub async fn track_metadata(handle: &TrackHandle) -> Metadata {
+    let mut meta = handle.metadata().clone();
+    let read = handle.typemap().read().await;
+    if let Some(live) = read.get::<TrackLive>() {
+        live.read().await.apply(&mut meta);
+    }
+    meta
+}
+
+/// The source of a track if it's a link. Library tracks have their `lib:` id instead,
+/// where the files are on the host isn't shown.
+pub fn source_link(url: Option<&str>) -> Option<&str> {
+    url.filter(|u| u.contains("://"))
+}
+
+/// `[title](url)`, or only the title when the source isn't a link
+pub fn linked_title(title: String, url: Option<&str>) -> String {
+    match source_link(url) {
+        Some(u) => format!("[{}]({})", title, u),
+        None => title,
+    }
+}
+
+/// Metadata of the track playing in a guild
+pub async fn current_metadata(manager: &Songbird, guild: GuildId) -> Option<Metadata> {
+    let handle = {
+        let lock = manager.get(guild)?;
+        let call = lock.lock().await;
+        call.queue().current()?
+    };
+    Some(track_metadata(&handle).await)
+}
+
+/// Reactions for answering `pick` with the first ten indices
+const KEYCAPS: [&str; 10] = [
+    "0\u{fe0f}\u{20e3}",
+    "1\u{fe0f}\u{20e3}",
+    "2\u{fe0f}\u{20e3}",
+    "3\u{fe0f}\u{20e3}",
+    "4\u{fe0f}\u{20e3}",
+    "5\u{fe0f}\u{20e3}",
+    "6\u{fe0f}\u{20e3}",
+    "7\u{fe0f}\u{20e3}",
+    "8\u{fe0f}\u{20e3}",
+    "9\u{fe0f}\u{20e3}",
+];
+
+/// Lists up to 16 options in an embed and waits for the author to answer with the index of
+/// one, by replying or reacting. `None` if they don't in time or answer anything else.
+pub async fn pick(ctx: &Context, msg: &Message, title: &str, options: &[String]) -> Option<usize> {
+    let options = &options[..options.len().min(16)];
+    let text = options
+        .iter()
+        .enumerate()
+        .map(|(i, o)| format!("`{}`: {}", i, o))
+        .collect::<Vec<String>>()
+        .join("\n");
+    let colour = cached_colour(ctx, msg.guild(&ctx.cache).await).await;
+    let prompt = msg
+        .channel_id
+        .send_message(&ctx, |m| {
+            m.embed(|e| {
+                e.title(title)
+                    .description(text)
+                    .colour(colour)
+                    .footer(|f| f.text("Reply with a number or react, anything else cancels"))
+            })
+        })
+        .await;
+    let prompt = match prompt {
+        Ok(p) => p,
+        Err(e) => {
+            warn!("Could not send/delete message: {}", e);
+            return None;
+        }
+    };
+
+    // One request per reaction, so they're added while waiting
+    let reacting = {
+        let http = ctx.http.clone();
+        let (channel, message) = (prompt.channel_id.0, prompt.id.0);
+        let count = options.len().min(KEYCAPS.len());
+        tokio::spawn(async move {
+            for k in &KEYCAPS[..count] {
+                let emoji = ReactionType::Unicode(k.to_string());
+                if http
+                    .create_reaction(channel, message, &emoji)
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        })
+    };
+
+    let timeout = Duration::from_secs(30);
+    let answer = tokio::select! {
+        reply = msg.author.await_reply(&ctx).channel_id(msg.channel_id).timeout(timeout) => {
+            reply.and_then(|r| r.content.trim().parse::<usize>().ok())
+        }
+        reaction = prompt.await_reaction(&ctx).author_id(msg.author.id).timeout(timeout) => {
+            reaction.and_then(|r| match &r.as_inner_ref().emoji {
+                ReactionType::Unicode(e) => KEYCAPS.iter().position(|k| k == e),
+                _ => None,
+            })
+        }
+    };
+    reacting.abort();
+    handle_message(prompt.delete(&ctx.http).await);
+
+    answer.filter(|i| *i < options.len())
+}
@@ -0,0 +1,70 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+
+/// Parses the date formats found in stream and video metadata:
+/// RFC 3339, RFC 2822 (Icecast's `stream_start`), ISO 8601 without the colon in
+/// the offset (`stream_start_iso8601`) and youtube-dl's `YYYYMMDD`
+pub fn parse(date: &str) -> Option<DateTime<Utc>> {
+    let date = date.trim();
+    if let Ok(d) = DateTime::parse_from_rfc3339(date)
+        .or_else(|_| DateTime::parse_from_rfc2822(date))
+        .or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%z"))
+    {
+        return Some(d.with_timezone(&Utc));
+    }
+
+    // Old Icecast versions use zone names, which chrono can't parse; assume UTC
+    date.get(..25)
+        .and_then(|d| NaiveDateTime::parse_from_str(d, "%a, %d %b %Y %H:%M:%S").ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(date, "%Y%m%d")
+                .ok()
+                .map(|d| d.and_hms(0, 0, 0))
+        })
+        .map(|d| DateTime::from_utc(d, Utc))
+}
+
+/// Like "3h12m", "2d5h" or "40m"
+pub fn format_duration(duration: Duration) -> String {
+    let mins = duration.num_minutes().max(0);
+    let (days, hours, mins) = (mins / 1440, mins / 60 % 24, mins % 60);
+    if days > 0 {
+        format!("{}d{}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h{}m", hours, mins)
+    } else {
+        format!("{}m", mins)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let lines = include_str!("../tests/fixtures/dates.txt")
+            .lines()
+            .filter(|l| !l.starts_with('#'));
+        for line in lines {
+            let mut parts = line.splitn(2, " | ");
+            let (input, expected) = (parts.next().unwrap(), parts.next().unwrap());
+            assert_eq!(
+                parse(input).map(|d| d.to_rfc3339()).as_deref(),
+                Some(expected.trim()),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(parse("yesterday"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(Duration::seconds(59)), "0m");
+        assert_eq!(format_duration(Duration::minutes(40)), "40m");
+        assert_eq!(format_duration(Duration::minutes(192)), "3h12m");
+        assert_eq!(format_duration(Duration::hours(53)), "2d5h");
+        assert_eq!(format_duration(Duration::seconds(-5)), "0m");
+    }
+}
@@ -0,0 +1,180 @@
+use serde::Deserialize;
+use serenity::prelude::TypeMapKey;
+use std::{
+    error::Error,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DirectoryConfig {
+    /// Listing in the `yp.xml` format, either a URL or a local file
+    pub url: String,
+    /// Seconds before the listing is downloaded again
+    pub refresh: u64,
+}
+
+impl Default for DirectoryConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://dir.xiph.org/yp.xml".to_owned(),
+            refresh: 3600,
+        }
+    }
+}
+
+impl TypeMapKey for DirectoryConfig {
+    type Value = DirectoryConfig;
+}
+
+/// A station in the directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct Station {
+    pub name: String,
+    pub url: String,
+    pub genre: Option<String>,
+    /// kbit/s
+    pub bitrate: Option<u64>,
+    pub current_song: Option<String>,
+}
+
+/// Parses an Icecast YP listing, skipping entries without a name or listen URL
+pub fn parse(body: &str) -> Result<Vec<Station>, roxmltree::Error> {
+    let doc = roxmltree::Document::parse(body)?;
+    let stations = doc
+        .root_element()
+        .children()
+        .filter(|c| c.has_tag_name("entry"))
+        .filter_map(|e| {
+            let field = |name| {
+                e.children()
+                    .find(|c| c.has_tag_name(name))
+                    .and_then(|c| c.text())
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_owned)
+            };
+            Some(Station {
+                name: field("server_name")?,
+                url: field("listen_url")?,
+                genre: field("genre"),
+                bitrate: field("bitrate").and_then(|b| b.parse().ok()),
+                current_song: field("current_song"),
+            })
+        })
+        .collect();
+    Ok(stations)
+}
+
+/// Stations matching any of the terms, best first.
+/// Name matches count more than genre ones and whole words more than parts of one.
+pub fn search<'a>(stations: &'a [Station], query: &str) -> Vec<&'a Station> {
+    let terms = query
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<String>>();
+    let score = |text: &str, weight: u32| {
+        let text = text.to_lowercase();
+        let words = text
+            .split(|c: char| !c.is_alphanumeric())
+            .collect::<Vec<&str>>();
+        terms
+            .iter()
+            .map(|t| {
+                if words.contains(&t.as_str()) {
+                    weight * 2
+                } else if text.contains(t.as_str()) {
+                    weight
+                } else {
+                    0
+                }
+            })
+            .sum::<u32>()
+    };
+
+    let mut ranked = stations
+        .iter()
+        .map(|s| {
+            let genre = s.genre.as_deref().map_or(0, |g| score(g, 1));
+            (score(&s.name, 2) + genre, s)
+        })
+        .filter(|(score, _)| *score > 0)
+        .collect::<Vec<_>>();
+    // Stable, so equally good ones keep the directory's order
+    ranked.sort_by(|a, b| b.0.cmp(&a.0));
+    ranked.into_iter().map(|(_, s)| s).collect()
+}
+
+/// The downloaded listing, kept for `refresh` seconds since it's a few MiB
+#[derive(Default)]
+pub struct Directory(Mutex<Option<(Instant, Arc<Vec<Station>>)>>);
+
+impl TypeMapKey for Directory {
+    type Value = Arc<Directory>;
+}
+
+impl Directory {
+    pub async fn stations(
+        &self,
+        config: &DirectoryConfig,
+    ) -> Result<Arc<Vec<Station>>, Box<dyn Error + Send + Sync>> {
+        let mut cached = self.0.lock().await;
+        if let Some((fetched, stations)) = &*cached {
+            if fetched.elapsed() < Duration::from_secs(config.refresh) {
+                return Ok(stations.clone());
+            }
+        }
+
+        let body = if config.url.starts_with("http") {
+            reqwest::get(&config.url)
+                .await?
+                .error_for_status()?
+                .text()
+                .await?
+        } else {
+            tokio::fs::read_to_string(&config.url).await?
+        };
+        let stations = Arc::new(parse(&body)?);
+        *cached = Some((Instant::now(), stations.clone()));
+        Ok(stations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yp() {
+        let stations = parse(include_str!("../tests/fixtures/yp.xml")).unwrap();
+        assert_eq!(stations.len(), 3);
+        assert_eq!(
+            stations[0],
+            Station {
+                name: "Jazz Lounge".to_owned(),
+                url: "http://jazz.example.com:8000/lounge.ogg".to_owned(),
+                genre: Some("jazz smooth".to_owned()),
+                bitrate: Some(128),
+                current_song: Some("Miles Davis - So What".to_owned()),
+            }
+        );
+        assert_eq!(stations[2].bitrate, None);
+    }
+
+    #[test]
+    fn ranking() {
+        let stations = parse(include_str!("../tests/fixtures/yp.xml")).unwrap();
+        let names = |q| {
+            search(&stations, q)
+                .into_iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<_>>()
+        };
+        // The name match beats the genre one
+        assert_eq!(names("jazz"), vec!["Jazz Lounge", "Late Night Radio"]);
+        assert_eq!(names("metal"), vec!["Metal Attack"]);
+        assert!(names("polka").is_empty());
+    }
+}
@@ -0,0 +1,434 @@
+use crate::tap::Packet;
+use ogg::{PacketWriteEndInfo, PacketWriter};
+use serde_json::{json, Value};
+use songbird::input::{cached::Compressed, Metadata};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Discord rejects attachments bigger than this in non-boosted guilds
+pub const UPLOAD_LIMIT: usize = 8 * 1024 * 1024;
+
+const SERIAL: u32 = 0x736f_646d;
+/// Packets in each page of a live stream, half a second
+const PAGE_PACKETS: u32 = 25;
+
+/// Where the Opus frames of a queued track can be read from
+#[derive(Debug)]
+pub enum OpusSource {
+    /// In-RAM store created when the track was added
+    Memory(Compressed),
+    /// DCA file in the audio cache
+    Dca(PathBuf),
+}
+
+impl Clone for OpusSource {
+    fn clone(&self) -> Self {
+        match self {
+            OpusSource::Memory(c) => OpusSource::Memory(c.new_handle()),
+            OpusSource::Dca(p) => OpusSource::Dca(p.clone()),
+        }
+    }
+}
+
+impl OpusSource {
+    /// Remux the whole track into an Ogg Opus file held in memory.
+    /// Blocks until the frames are available, call from a blocking thread.
+    pub fn to_ogg(self, meta: &Metadata) -> io::Result<Vec<u8>> {
+        match self {
+            OpusSource::Memory(c) => {
+                let channels = if c.stereo { 2 } else { 1 };
+                dca_to_ogg(c.raw, Vec::new(), channels, meta)
+            }
+            OpusSource::Dca(p) => {
+                let mut reader = BufReader::new(File::open(p)?);
+                let header = read_dca_header(&mut reader)?;
+                dca_to_ogg(reader, Vec::new(), header_channels(&header), meta)
+            }
+        }
+    }
+}
+
+/// Reads the magic bytes and the JSON metadata block of a DCA1 file,
+/// leaving the reader at the first frame
+pub fn read_dca_header<R: Read>(reader: &mut R) -> io::Result<Value> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != b"DCA1"[..] {
+        return Err(io::Error::new(ErrorKind::InvalidData, "Invalid magic bytes"));
+    }
+
+    let mut size = [0u8; 4];
+    reader.read_exact(&mut size)?;
+    let size = i32::from_le_bytes(size);
+    if size < 2 {
+        return Err(io::Error::new(ErrorKind::InvalidData, "Invalid metadata size"));
+    }
+
+    let mut json = vec![0u8; size as usize];
+    reader.read_exact(&mut json)?;
+    Ok(serde_json::from_slice(&json).unwrap_or_default())
+}
+
+/// Magic bytes and JSON metadata block of a DCA1 file, for files whose frames are
+/// appended as they come
+pub fn dca_header(channels: u8, meta: &Metadata) -> Vec<u8> {
+    let json = json!({
+        "dca": {
+            "version": 1,
+            "tool": {
+                "name": "sodmb",
+                "version": env!("CARGO_PKG_VERSION"),
+                "url": "https://github.com/techmccat/sodmb",
+                "author": "me",
+            },
+        },
+        "opus": {
+            "mode": "music",
+            "sample_rate": 48_000,
+            "frame_size": 960,
+            "abr": null,
+            "vbr": 1,
+            "channels": channels,
+        },
+        "info": { "title": meta.title, "artist": meta.artist },
+        "origin": { "source": "file", "url": meta.source_url },
+        "extra": { "date": meta.date },
+    })
+    .to_string();
+
+    let mut header = b"DCA1".to_vec();
+    header.extend_from_slice(&(json.len() as i32).to_le_bytes());
+    header.extend_from_slice(json.as_bytes());
+    header
+}
+
+fn header_channels(header: &Value) -> u8 {
+    header
+        .get("opus")
+        .and_then(|o| o.get("channels"))
+        .and_then(Value::as_u64)
+        .map(|c| c as u8)
+        .unwrap_or(2)
+}
+
+/// Copies DCA-framed Opus packets (i16 length + data) into an Ogg container,
+/// no decoding or re-encoding involved
+pub fn dca_to_ogg<R: Read, W: Write>(
+    mut frames: R,
+    out: W,
+    channels: u8,
+    meta: &Metadata,
+) -> io::Result<W> {
+    let mut writer = PacketWriter::new(out);
+
+    writer.write_packet(
+        opus_head(channels).into_boxed_slice(),
+        SERIAL,
+        PacketWriteEndInfo::EndPage,
+        0,
+    )?;
+    writer.write_packet(
+        opus_tags(meta).into_boxed_slice(),
+        SERIAL,
+        PacketWriteEndInfo::EndPage,
+        0,
+    )?;
+
+    let mut granule = 0;
+    // A packet can only be written once we know whether it's the last one
+    let mut pending = next_frame(&mut frames)?;
+    while let Some(packet) = pending {
+        granule += packet_samples(&packet);
+        pending = next_frame(&mut frames)?;
+        let info = if pending.is_some() {
+            PacketWriteEndInfo::NormalPacket
+        } else {
+            PacketWriteEndInfo::EndStream
+        };
+        writer.write_packet(packet.into_boxed_slice(), SERIAL, info, granule)?;
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Muxes an endless sequence of Opus packets into a chained Ogg stream, starting a
+/// new link whenever the tags change so that players show the new title
+pub struct OggStream {
+    writer: PacketWriter<Vec<u8>>,
+    serial: u32,
+    granule: u64,
+    in_page: u32,
+    /// Held back until it's known whether it ends the link
+    pending: Option<Packet>,
+    started: bool,
+}
+
+impl Default for OggStream {
+    fn default() -> Self {
+        Self {
+            writer: PacketWriter::new(Vec::new()),
+            serial: SERIAL,
+            granule: 0,
+            in_page: 0,
+            pending: None,
+            started: false,
+        }
+    }
+}
+
+impl OggStream {
+    /// Ends the current link and starts a new one tagged with `meta`
+    pub fn start_link(&mut self, meta: &Metadata) -> io::Result<()> {
+        if let Some(p) = self.pending.take() {
+            self.granule += packet_samples(&p);
+            self.writer.write_packet(
+                p.to_vec().into_boxed_slice(),
+                self.serial,
+                PacketWriteEndInfo::EndStream,
+                self.granule,
+            )?;
+        }
+        if self.started {
+            // Each link needs its own serial number
+            self.serial = self.serial.wrapping_add(1);
+        }
+        self.started = true;
+        self.granule = 0;
+        self.in_page = 0;
+
+        self.writer.write_packet(
+            opus_head(2).into_boxed_slice(),
+            self.serial,
+            PacketWriteEndInfo::EndPage,
+            0,
+        )?;
+        self.writer.write_packet(
+            opus_tags(meta).into_boxed_slice(),
+            self.serial,
+            PacketWriteEndInfo::EndPage,
+            0,
+        )
+    }
+
+    pub fn push(&mut self, packet: Packet) -> io::Result<()> {
+        if !self.started {
+            self.start_link(&Metadata::default())?;
+        }
+        if let Some(p) = self.pending.replace(packet) {
+            self.granule += packet_samples(&p);
+            self.in_page += 1;
+            // Pages are only written once full, keep them short to keep latency low
+            let info = if self.in_page >= PAGE_PACKETS {
+                self.in_page = 0;
+                PacketWriteEndInfo::EndPage
+            } else {
+                PacketWriteEndInfo::NormalPacket
+            };
+            self.writer.write_packet(
+                p.to_vec().into_boxed_slice(),
+                self.serial,
+                info,
+                self.granule,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Bytes of the pages completed since the last call
+    pub fn take(&mut self) -> Vec<u8> {
+        std::mem::take(self.writer.inner_mut())
+    }
+}
+
+fn next_frame<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 2];
+    match reader.read_exact(&mut len) {
+        Ok(_) => (),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = i16::from_le_bytes(len);
+    if len < 0 {
+        return Err(io::Error::new(ErrorKind::InvalidData, "Negative frame size"));
+    }
+
+    let mut frame = vec![0u8; len as usize];
+    reader.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+fn opus_head(channels: u8) -> Vec<u8> {
+    let mut head = Vec::with_capacity(19);
+    head.extend_from_slice(b"OpusHead");
+    head.push(1);
+    head.push(channels);
+    // pre-skip
+    head.extend_from_slice(&0u16.to_le_bytes());
+    head.extend_from_slice(&48_000u32.to_le_bytes());
+    // output gain
+    head.extend_from_slice(&0i16.to_le_bytes());
+    // mapping family
+    head.push(0);
+    head
+}
+
+fn opus_tags(meta: &Metadata) -> Vec<u8> {
+    let vendor = concat!("sodmb ", env!("CARGO_PKG_VERSION"));
+    let comments: Vec<String> = meta
+        .title
+        .iter()
+        .map(|t| format!("TITLE={}", t))
+        .chain(meta.artist.iter().map(|a| format!("ARTIST={}", a)))
+        .chain(meta.source_url.iter().map(|u| format!("LOCATION={}", u)))
+        .collect();
+
+    let mut tags = Vec::new();
+    tags.extend_from_slice(b"OpusTags");
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor.as_bytes());
+    tags.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for c in comments {
+        tags.extend_from_slice(&(c.len() as u32).to_le_bytes());
+        tags.extend_from_slice(c.as_bytes());
+    }
+    tags
+}
+
+/// Number of 48kHz samples in an Opus packet, from its TOC byte (RFC 6716 3.1)
+fn packet_samples(packet: &[u8]) -> u64 {
+    let toc = match packet.first() {
+        Some(t) => *t,
+        None => return 0,
+    };
+    let config = toc >> 3;
+    // frame duration in units of 2.5ms
+    let frame = match config {
+        0..=11 => [4, 8, 16, 24][(config % 4) as usize],
+        12..=15 => [4, 8][(config % 2) as usize],
+        _ => [1, 2, 4, 8][(config % 4) as usize],
+    };
+    let count = match toc & 3 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => packet.get(1).map(|c| c & 0x3f).unwrap_or(0) as u64,
+    };
+    frame * 120 * count
+}
+
+/// Channels of an Opus packet, from the stereo flag of its TOC byte
+pub fn packet_channels(packet: &[u8]) -> u8 {
+    match packet.first() {
+        Some(toc) if toc & 0x04 != 0 => 2,
+        _ => 1,
+    }
+}
+
+/// Title, artist and URL stored in a DCA header
+fn header_metadata(header: &Value) -> Metadata {
+    let info = header.get("info");
+    Metadata {
+        title: info
+            .and_then(|i| i.get("title"))
+            .and_then(Value::as_str)
+            .map(str::to_owned),
+        artist: info
+            .and_then(|i| i.get("artist"))
+            .and_then(Value::as_str)
+            .map(str::to_owned),
+        source_url: header
+            .get("origin")
+            .and_then(|o| o.get("url"))
+            .and_then(Value::as_str)
+            .map(str::to_owned),
+        ..Default::default()
+    }
+}
+
+/// Remux a DCA file into Ogg Opus, tagged with the metadata in its header
+pub fn dca_file_to_ogg<W: Write>(path: &Path, out: W) -> io::Result<W> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = read_dca_header(&mut reader)?;
+    dca_to_ogg(
+        reader,
+        out,
+        header_channels(&header),
+        &header_metadata(&header),
+    )
+}
+
+/// `sodmb export <file.dca> [file.ogg]`
+pub fn cli<I: Iterator<Item = String>>(mut args: I) -> Result<(), Box<dyn std::error::Error>> {
+    let input = args
+        .next()
+        .ok_or("Usage: sodmb export <file.dca> [file.ogg]")?;
+    let output = args
+        .next()
+        .unwrap_or_else(|| format!("{}.ogg", input.trim_end_matches(".dca")));
+
+    let out = BufWriter::new(File::create(&output)?);
+    dca_file_to_ogg(Path::new(&input), out)?.flush()?;
+    println!("Wrote {}", output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dca_file() {
+        let mut reader = &include_bytes!("../tests/fixtures/mono.dca")[..];
+        let header = read_dca_header(&mut reader).unwrap();
+        assert_eq!(header_channels(&header), 1);
+        let meta = header_metadata(&header);
+        assert_eq!(meta.title.as_deref(), Some("Song"));
+        assert_eq!(meta.artist.as_deref(), Some("Band"));
+        assert_eq!(meta.source_url.as_deref(), Some("https://example.com/song"));
+
+        assert_eq!(
+            next_frame(&mut reader).unwrap(),
+            Some(vec![0xfc, 0xff, 0xfe])
+        );
+        assert_eq!(
+            next_frame(&mut reader).unwrap(),
+            Some(vec![0x03, 0x02, 0xaa, 0xbb])
+        );
+        assert_eq!(next_frame(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let meta = Metadata {
+            title: Some("Song".to_owned()),
+            ..Default::default()
+        };
+        let bytes = dca_header(1, &meta);
+        let header = read_dca_header(&mut &bytes[..]).unwrap();
+        assert_eq!(header_channels(&header), 1);
+        assert_eq!(header_metadata(&header).title.as_deref(), Some("Song"));
+
+        assert!(read_dca_header(&mut &b"OggS\0\0\0\0"[..]).is_err());
+    }
+
+    #[test]
+    fn samples() {
+        // CELT 20ms, one frame
+        assert_eq!(packet_samples(&[0xfc]), 960);
+        // CELT 2.5ms, two frames
+        assert_eq!(packet_samples(&[0x81]), 240);
+        // SILK 10ms, frame count in the second byte
+        assert_eq!(packet_samples(&[0x03, 0x02]), 960);
+        // Hybrid 20ms
+        assert_eq!(packet_samples(&[0x68]), 960);
+        assert_eq!(packet_samples(&[]), 0);
+    }
+
+    #[test]
+    fn channels() {
+        assert_eq!(packet_channels(&[0xfc]), 2);
+        assert_eq!(packet_channels(&[0xf8]), 1);
+    }
+}
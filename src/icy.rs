@@ -0,0 +1,601 @@
+use crate::{
+    auth::{self, Credentials},
+    station::StationInfo,
+};
+use serde::Deserialize;
+use serenity::prelude::TypeMapKey;
+use songbird::input::{error::Result, Codec, Container, Input, Metadata, Reader};
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, Receiver, TryRecvError},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, RwLock};
+use tracing::{debug, info};
+
+/// Number of titles kept in `LiveMetadata::history`
+pub const HISTORY_LEN: usize = 10;
+/// Longest wait between two reconnection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Size of a stereo f32 sample in ffmpeg's output
+const FRAME_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StreamConfig {
+    /// Seconds to keep trying to reconnect a dropped live stream, 0 to disable
+    pub reconnect_timeout: u64,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            reconnect_timeout: 60,
+        }
+    }
+}
+
+impl TypeMapKey for StreamConfig {
+    type Value = StreamConfig;
+}
+
+/// What to do when a live stream drops
+#[derive(Clone)]
+pub struct Reconnect {
+    /// Gives up once the stream has been down for this long
+    pub timeout: Duration,
+    /// Posts a notice for the users
+    pub notify: Arc<dyn Fn(String) + Send + Sync>,
+}
+
+/// Song info of a live stream, updated while the track plays
+#[derive(Debug, Default, Clone)]
+pub struct LiveMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub station: StationInfo,
+    /// Songs played on the stream, newest first
+    pub history: VecDeque<String>,
+}
+
+pub type SharedLive = Arc<RwLock<LiveMetadata>>;
+
+impl LiveMetadata {
+    /// Replace the fields of a track's static metadata with the live ones
+    pub fn apply(&self, meta: &mut Metadata) {
+        if self.title.is_some() {
+            meta.title = self.title.clone();
+            meta.artist = self.artist.clone();
+        }
+    }
+
+    /// Sets the song on air, returns true if it changed
+    pub fn set_song(&mut self, title: Option<String>, artist: Option<String>) -> bool {
+        if title.is_none() || (title == self.title && artist == self.artist) {
+            return false;
+        }
+        self.title = title;
+        self.artist = artist;
+        if let Some(t) = self.display_title() {
+            self.history.push_front(t);
+            self.history.truncate(HISTORY_LEN);
+        }
+        true
+    }
+
+    /// "Artist - Title", or just the title
+    pub fn display_title(&self) -> Option<String> {
+        match (&self.artist, &self.title) {
+            (Some(a), Some(t)) => Some(format!("{} - {}", a, t)),
+            (None, Some(t)) => Some(t.clone()),
+            _ => None,
+        }
+    }
+}
+
+enum State {
+    Audio(usize),
+    Length,
+    Meta(usize),
+}
+
+/// Splits a stream with `icy-metaint` set into audio and metadata blocks
+struct Demuxer {
+    metaint: usize,
+    state: State,
+    buf: Vec<u8>,
+}
+
+impl Demuxer {
+    fn new(metaint: usize) -> Self {
+        Self {
+            metaint,
+            state: State::Audio(metaint),
+            buf: Vec::new(),
+        }
+    }
+
+    fn feed(&mut self, mut chunk: &[u8], audio: &mut Vec<u8>, meta: &mut Vec<String>) {
+        while !chunk.is_empty() {
+            match self.state {
+                State::Audio(left) => {
+                    let n = left.min(chunk.len());
+                    audio.extend_from_slice(&chunk[..n]);
+                    chunk = &chunk[n..];
+                    self.state = if n == left {
+                        State::Length
+                    } else {
+                        State::Audio(left - n)
+                    };
+                }
+                State::Length => {
+                    let len = chunk[0] as usize * 16;
+                    chunk = &chunk[1..];
+                    self.buf.clear();
+                    self.state = if len == 0 {
+                        State::Audio(self.metaint)
+                    } else {
+                        State::Meta(len)
+                    };
+                }
+                State::Meta(left) => {
+                    let n = left.min(chunk.len());
+                    self.buf.extend_from_slice(&chunk[..n]);
+                    chunk = &chunk[n..];
+                    self.state = if n == left {
+                        meta.push(
+                            String::from_utf8_lossy(&self.buf)
+                                .trim_end_matches('\0')
+                                .to_owned(),
+                        );
+                        State::Audio(self.metaint)
+                    } else {
+                        State::Meta(left - n)
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// Extracts `StreamTitle` from a metadata block like `StreamTitle='A - B';StreamUrl='';`
+fn stream_title(block: &str) -> Option<&str> {
+    let start = block.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &block[start..];
+    // titles can contain quotes, the field ends at the first `';`
+    let end = rest.find("';").unwrap_or_else(|| rest.trim_end_matches('\'').len());
+    Some(&rest[..end])
+}
+
+/// Most stations send "Artist - Title", returns (title, artist)
+pub fn split_title(title: &str) -> (Option<String>, Option<String>) {
+    let title = title.trim();
+    if title.is_empty() {
+        return (None, None);
+    }
+    match title.find(" - ") {
+        Some(i) => (Some(title[i + 3..].to_owned()), Some(title[..i].to_owned())),
+        None => (Some(title.to_owned()), None),
+    }
+}
+
+fn io_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// Requests the stream with `Icy-MetaData: 1`, returning the metadata interval if
+/// the server interleaves metadata
+async fn connect(
+    url: &str,
+    auth: Option<&Credentials>,
+) -> reqwest::Result<(reqwest::Response, Option<Demuxer>)> {
+    let response = auth::basic_auth(reqwest::Client::new().get(url), auth)
+        .header("Icy-MetaData", "1")
+        .send()
+        .await?
+        .error_for_status()?;
+    let demuxer = response
+        .headers()
+        .get("icy-metaint")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|m| m.parse::<usize>().ok())
+        .filter(|m| *m > 0)
+        .map(Demuxer::new);
+    Ok((response, demuxer))
+}
+
+/// Reopens a dropped stream, waiting twice as long after each failed attempt.
+/// `None` once `timeout` has passed or the track is gone.
+async fn reopen(
+    url: &str,
+    auth: Option<&Credentials>,
+    timeout: Duration,
+    tx: &mpsc::Sender<Vec<u8>>,
+) -> Option<(reqwest::Response, Option<Demuxer>)> {
+    let start = Instant::now();
+    let mut delay = Duration::from_secs(1);
+    loop {
+        let left = timeout.checked_sub(start.elapsed())?;
+        tokio::time::sleep(delay.min(left)).await;
+        if tx.is_closed() {
+            return None;
+        }
+        match connect(url, auth).await {
+            Ok(r) => return Some(r),
+            Err(e) => debug!("Couldn't reconnect to {}: {}", url, e),
+        }
+        delay = (delay * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Reads ffmpeg's output on its own thread. While the stream is reconnecting it
+/// returns silence instead of blocking the driver, so the call can still be
+/// paused or skipped.
+struct LiveReader {
+    child: Child,
+    rx: Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    read: usize,
+    /// Bytes of audio returned so far
+    pos: usize,
+    reconnecting: Arc<AtomicBool>,
+}
+
+impl LiveReader {
+    fn new(mut child: Child, reconnecting: Arc<AtomicBool>) -> Self {
+        let mut stdout = child.stdout.take().unwrap();
+        let (tx, rx) = sync_channel(16);
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 8192];
+            loop {
+                match stdout.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if tx.send(buf[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            child,
+            rx,
+            buf: Vec::new(),
+            read: 0,
+            pos: 0,
+            reconnecting,
+        }
+    }
+}
+
+impl Read for LiveReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.read == self.buf.len() {
+            // Silence has to start on a sample boundary to keep the rest aligned
+            let silence = out.len() - out.len() % FRAME_SIZE;
+            let next = if self.reconnecting.load(Ordering::Relaxed)
+                && self.pos % FRAME_SIZE == 0
+                && silence > 0
+            {
+                match self.rx.try_recv() {
+                    Ok(b) => Some(b),
+                    Err(TryRecvError::Empty) => {
+                        out[..silence].iter_mut().for_each(|b| *b = 0);
+                        return Ok(silence);
+                    }
+                    Err(TryRecvError::Disconnected) => None,
+                }
+            } else {
+                self.rx.recv().ok()
+            };
+
+            match next {
+                Some(b) => {
+                    self.buf = b;
+                    self.read = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let n = (self.buf.len() - self.read).min(out.len());
+        out[..n].copy_from_slice(&self.buf[self.read..self.read + n]);
+        self.read += n;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Drop for LiveReader {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Opens a live stream, piping it through ffmpeg to read the in-band titles into the
+/// returned `SharedLive` and to reconnect when it drops. Returns `None` if the server
+/// doesn't look like a radio, in which case the URL can be given to ffmpeg as is.
+pub async fn open(
+    url: &str,
+    auth: Option<Credentials>,
+    mut meta: Metadata,
+    reconnect: Reconnect,
+) -> Result<Option<(Input, SharedLive)>> {
+    let (mut response, mut demuxer) = connect(url, auth.as_ref()).await.map_err(io_error)?;
+
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned)
+    };
+    if demuxer.is_none() && header("icy-name").is_none() && header("icy-br").is_none() {
+        return Ok(None);
+    }
+    if meta.title.is_none() {
+        meta.title = header("icy-name");
+    }
+    let station = StationInfo {
+        name: header("icy-name"),
+        genre: header("icy-genre"),
+        description: header("icy-description"),
+        listeners: None,
+        bitrate: header("icy-br").and_then(|b| b.split(',').next()?.trim().parse().ok()),
+        started: None,
+    };
+    if meta.source_url.is_none() {
+        meta.source_url = Some(url.to_owned());
+    }
+
+    let reconnecting = Arc::new(AtomicBool::new(false));
+    let (reader, tx) = pipe(reconnecting.clone())?;
+
+    let live = SharedLive::new(RwLock::new(LiveMetadata {
+        station,
+        ..Default::default()
+    }));
+
+    let task_live = live.clone();
+    let url = url.to_owned();
+    tokio::spawn(async move {
+        let mut blocks = Vec::new();
+        loop {
+            let chunk = match response.chunk().await {
+                Ok(Some(c)) => c,
+                other => {
+                    if let Err(e) = other {
+                        info!("Stream {} dropped: {}", url, e);
+                    }
+                    if reconnect.timeout.as_secs() == 0 || tx.is_closed() {
+                        break;
+                    }
+
+                    let name = task_live
+                        .read()
+                        .await
+                        .station
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| url.clone());
+                    reconnecting.store(true, Ordering::Relaxed);
+                    (reconnect.notify)(format!("Lost connection to {}, reconnecting", name));
+
+                    match reopen(&url, auth.as_ref(), reconnect.timeout, &tx).await {
+                        Some((r, d)) => {
+                            info!("Reconnected to {}", url);
+                            (reconnect.notify)(format!("Reconnected to {}", name));
+                            reconnecting.store(false, Ordering::Relaxed);
+                            response = r;
+                            demuxer = d;
+                            continue;
+                        }
+                        None => {
+                            if !tx.is_closed() {
+                                (reconnect.notify)(format!(
+                                    "Couldn't reconnect to {}, skipping",
+                                    name
+                                ));
+                            }
+                            break;
+                        }
+                    }
+                }
+            };
+
+            let audio = match &mut demuxer {
+                Some(d) => {
+                    let mut audio = Vec::with_capacity(chunk.len());
+                    d.feed(&chunk, &mut audio, &mut blocks);
+                    audio
+                }
+                None => chunk.to_vec(),
+            };
+
+            for block in blocks.drain(..) {
+                if let Some(t) = stream_title(&block) {
+                    debug!("New title on {}: {}", url, t);
+                    let (title, artist) = split_title(t);
+                    task_live.write().await.set_song(title, artist);
+                }
+            }
+            // ffmpeg has exited, the track is over
+            if tx.send(audio).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(Some((input(reader, meta), live)))
+}
+
+/// Downloads a private file or stream for ffmpeg, so the credentials stay off its
+/// command line. Unlike `open` it doesn't reconnect.
+pub async fn open_private(url: &str, auth: &Credentials, meta: Metadata) -> Result<Input> {
+    let (mut response, mut demuxer) = connect(url, Some(auth)).await.map_err(io_error)?;
+    let (reader, tx) = pipe(Arc::new(AtomicBool::new(false)))?;
+
+    let url = url.to_owned();
+    tokio::spawn(async move {
+        let mut blocks = Vec::new();
+        loop {
+            let chunk = match response.chunk().await {
+                Ok(Some(c)) => c,
+                Ok(None) => break,
+                Err(e) => {
+                    info!("Stream {} dropped: {}", url, e);
+                    break;
+                }
+            };
+            let audio = match &mut demuxer {
+                Some(d) => {
+                    let mut audio = Vec::with_capacity(chunk.len());
+                    d.feed(&chunk, &mut audio, &mut blocks);
+                    blocks.clear();
+                    audio
+                }
+                None => chunk.to_vec(),
+            };
+            if tx.send(audio).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(input(reader, meta))
+}
+
+/// Starts ffmpeg decoding the audio sent to the returned channel
+fn pipe(reconnecting: Arc<AtomicBool>) -> io::Result<(LiveReader, mpsc::Sender<Vec<u8>>)> {
+    let mut child = Command::new("ffmpeg")
+        .args(&["-i", "pipe:0"])
+        .args(&[
+            "-f",
+            "s16le",
+            "-ac",
+            "2",
+            "-ar",
+            "48000",
+            "-acodec",
+            "pcm_f32le",
+            "-",
+        ])
+        .stderr(Stdio::null())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let reader = LiveReader::new(child, reconnecting);
+    let (tx, mut rx) = mpsc::channel::<Vec<u8>>(16);
+
+    // ChildStdin is blocking, keep it off the runtime
+    std::thread::spawn(move || {
+        while let Some(buf) = rx.blocking_recv() {
+            if stdin.write_all(&buf).is_err() {
+                break;
+            }
+        }
+    });
+    Ok((reader, tx))
+}
+
+fn input(reader: LiveReader, meta: Metadata) -> Input {
+    Input::new(
+        true,
+        Reader::Extension(Box::new(reader)),
+        Codec::FloatPcm,
+        Container::Raw,
+        Some(meta),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stream with `metaint` bytes of audio before each metadata block, and the audio
+    /// alone
+    fn stream(metaint: usize, blocks: &[&str]) -> (Vec<u8>, Vec<u8>) {
+        let (mut stream, mut audio) = (Vec::new(), Vec::new());
+        for (i, block) in blocks.iter().enumerate() {
+            let chunk = (0..metaint)
+                .map(|n| (i * metaint + n) as u8)
+                .collect::<Vec<u8>>();
+            stream.extend_from_slice(&chunk);
+            audio.extend_from_slice(&chunk);
+
+            // Blocks are padded with zeros to a multiple of 16 bytes
+            let len = (block.len() + 15) / 16;
+            stream.push(len as u8);
+            stream.extend_from_slice(block.as_bytes());
+            stream.resize(stream.len() + len * 16 - block.len(), 0);
+        }
+        // Cut off before the next block
+        stream.extend_from_slice(&[1, 2, 3]);
+        audio.extend_from_slice(&[1, 2, 3]);
+        (stream, audio)
+    }
+
+    #[test]
+    fn demuxer() {
+        let blocks = [
+            "StreamTitle='Artist - Song';StreamUrl='';",
+            "",
+            "StreamTitle='Rock; Roll';",
+            // No padding needed
+            "StreamTitle='Fills the whole block, no padding';",
+        ];
+        let titles = [blocks[0], blocks[2], blocks[3]];
+        let (stream, expected) = stream(37, &blocks);
+        for size in &[1, 3, 7, 16, 38, 1000] {
+            let mut demuxer = Demuxer::new(37);
+            let (mut audio, mut meta) = (Vec::new(), Vec::new());
+            for chunk in stream.chunks(*size) {
+                demuxer.feed(chunk, &mut audio, &mut meta);
+            }
+            assert_eq!(audio, expected, "{} byte chunks", size);
+            // Empty blocks mean the title didn't change
+            assert_eq!(meta, titles, "{} byte chunks", size);
+        }
+    }
+
+    #[test]
+    fn titles() {
+        let title = |block| stream_title(block).map(split_title);
+        let owned = |s: &str| Some(s.to_owned());
+
+        assert_eq!(
+            title("StreamTitle='Artist - Song';StreamUrl='http://example.com';"),
+            Some((owned("Song"), owned("Artist")))
+        );
+        assert_eq!(
+            title("StreamTitle='Guns N' Roses - Don't Cry';"),
+            Some((owned("Don't Cry"), owned("Guns N' Roses")))
+        );
+        assert_eq!(
+            title("StreamTitle='Rock; Roll';"),
+            Some((owned("Rock; Roll"), None))
+        );
+        // Only the first dash separates the artist
+        assert_eq!(
+            title("StreamTitle='A - B - C';"),
+            Some((owned("B - C"), owned("A")))
+        );
+        assert_eq!(
+            title("StreamTitle='Hyphen-ated';"),
+            Some((owned("Hyphen-ated"), None))
+        );
+        assert_eq!(title("StreamTitle='No end'"), Some((owned("No end"), None)));
+        assert_eq!(title("StreamTitle='';"), Some((None, None)));
+        assert_eq!(title("StreamUrl='';"), None);
+    }
+}
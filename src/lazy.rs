@@ -0,0 +1,115 @@
+use futures::{
+    channel::oneshot::{self, Receiver, Sender},
+    future::BoxFuture,
+};
+use songbird::input::{Codec, Container, Input, Metadata, Reader};
+use std::{
+    io::{self, Read},
+    sync::{Arc, Mutex},
+};
+use tokio::runtime::Handle;
+
+/// Opens the real input, failing with a message for the user
+pub type Open = BoxFuture<'static, Result<Input, String>>;
+
+/// An input that's only opened when `Prefetch::start` is called or it starts playing,
+/// with `meta` until then. It plays silence while it's being opened.
+pub fn input(meta: Metadata, open: Open) -> (Input, Prefetch) {
+    let (tx, rx) = oneshot::channel();
+    let prefetch = Prefetch {
+        open: Arc::new(Mutex::new(Some((open, tx)))),
+        handle: Handle::current(),
+    };
+    let reader = LazyReader {
+        prefetch: prefetch.clone(),
+        rx,
+        input: None,
+        partial: Vec::new(),
+    };
+    let input = Input::new(
+        true,
+        Reader::Extension(Box::new(reader)),
+        Codec::FloatPcm,
+        Container::Raw,
+        Some(meta),
+    );
+    (input, prefetch)
+}
+
+/// Starts opening a lazy input ahead of time, so it's ready when it plays
+#[derive(Clone)]
+pub struct Prefetch {
+    open: Arc<Mutex<Option<(Open, Sender<Result<Input, String>>)>>>,
+    handle: Handle,
+}
+
+impl Prefetch {
+    /// Does nothing if it's already been started
+    pub fn start(&self) {
+        let open = self.open.lock().ok().and_then(|mut o| o.take());
+        if let Some((open, tx)) = open {
+            self.handle.spawn(async move {
+                let _ = tx.send(open.await);
+            });
+        }
+    }
+}
+
+struct LazyReader {
+    prefetch: Prefetch,
+    rx: Receiver<Result<Input, String>>,
+    input: Option<Input>,
+    /// Start of a mono sample split across two reads
+    partial: Vec<u8>,
+}
+
+impl Read for LazyReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        match &mut self.input {
+            Some(input) if input.stereo => return input.read(out),
+            // The input was declared stereo before it was known
+            Some(input) => return upmix(input, &mut self.partial, out),
+            None => (),
+        }
+        self.prefetch.start();
+        match self.rx.try_recv() {
+            Ok(Some(Ok(input))) => {
+                self.input = Some(input);
+                self.read(out)
+            }
+            // Ends the track, which skips to the next one
+            Ok(Some(Err(e))) => Err(io::Error::new(io::ErrorKind::Other, e)),
+            // Silence while it opens, like a lazy `Restartable`
+            Ok(None) => {
+                for b in out.iter_mut() {
+                    *b = 0;
+                }
+                Ok(out.len())
+            }
+            Err(_) => Err(io::Error::new(io::ErrorKind::Other, "input task gone")),
+        }
+    }
+}
+
+/// Reads float samples from a mono input, writing each one twice. `out` has to fit a
+/// stereo sample, the driver always reads whole frames.
+fn upmix(input: &mut impl Read, partial: &mut Vec<u8>, out: &mut [u8]) -> io::Result<usize> {
+    let mut mono = std::mem::take(partial);
+    let mut len = mono.len();
+    mono.resize((out.len() / 8 * 4).max(4), 0);
+    while len < 4 {
+        let n = input.read(&mut mono[len..])?;
+        if n == 0 {
+            return Ok(0);
+        }
+        len += n;
+    }
+
+    let whole = len / 4 * 4;
+    partial.extend_from_slice(&mono[whole..len]);
+    for (sample, pair) in mono[..whole].chunks_exact(4).zip(out.chunks_exact_mut(8)) {
+        pair[..4].copy_from_slice(sample);
+        pair[4..].copy_from_slice(sample);
+    }
+    Ok(whole * 2)
+}
@@ -0,0 +1,409 @@
+use serde::Deserialize;
+use serde_json::Value;
+use serenity::prelude::TypeMapKey;
+use sqlx::{
+    any::{AnyConnection, AnyRow},
+    Connection, Executor, Row,
+};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    error::Error,
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+use tokio::{process::Command, sync::Mutex};
+use tracing::{debug, info, warn};
+
+type DbResult<T> = Result<T, sqlx::Error>;
+
+/// Extensions of the files that get scanned
+const EXTENSIONS: &[&str] = &[
+    "aac", "flac", "m4a", "mka", "mp3", "ogg", "opus", "wav", "webm", "wma",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LibraryConfig {
+    /// Directories scanned for music, with their subdirectories
+    pub dirs: Vec<PathBuf>,
+    /// Where embedded covers are extracted to
+    pub covers: PathBuf,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            dirs: Vec::new(),
+            covers: "audio_cache/covers".into(),
+        }
+    }
+}
+
+impl TypeMapKey for LibraryConfig {
+    type Value = LibraryConfig;
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub id: i64,
+    pub path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+    /// File name in the covers directory
+    pub cover: Option<String>,
+}
+
+impl Track {
+    /// The tagged title, or the file name for untagged files
+    pub fn title(&self) -> String {
+        self.title.clone().unwrap_or_else(|| {
+            Path::new(&self.path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| self.path.clone())
+        })
+    }
+
+    fn from_row(row: &AnyRow) -> DbResult<Track> {
+        Ok(Track {
+            id: row.try_get("Id")?,
+            path: row.try_get("Path")?,
+            title: row.try_get("Title")?,
+            artist: row.try_get("Artist")?,
+            album: row.try_get("Album")?,
+            duration: row
+                .try_get::<Option<i64>, _>("Duration")?
+                .map(|ms| Duration::from_millis(ms as u64)),
+            cover: row.try_get("Cover")?,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ScanStats {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+}
+
+/// Tags of the music files on the host, kept up to date by `scan`
+#[derive(Debug, Clone)]
+pub struct Library {
+    connection: Arc<Mutex<AnyConnection>>,
+    scanning: Arc<AtomicBool>,
+}
+
+impl TypeMapKey for Library {
+    type Value = Library;
+}
+
+impl Library {
+    pub async fn new(uri: &str) -> DbResult<Library> {
+        let mut conn = AnyConnection::connect(uri).await?;
+        conn.execute(
+            "
+create table if not exists Library (
+    Id integer primary key,
+    Path text not null unique,
+    Title text,
+    Artist text,
+    Album text,
+    Duration integer,
+    Cover text,
+    Modified integer not null
+)
+            ",
+        )
+        .await?;
+        Ok(Library {
+            connection: Arc::new(Mutex::new(conn)),
+            scanning: Arc::default(),
+        })
+    }
+
+    pub async fn get(&self, id: i64) -> DbResult<Option<Track>> {
+        let mut conn = self.connection.lock().await;
+
+        let row = sqlx::query("select * from Library where Id = ?")
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        row.as_ref().map(Track::from_row).transpose()
+    }
+
+    /// Tracks with every word of the query in their tags or path
+    pub async fn search(&self, query: &str, limit: usize) -> DbResult<Vec<Track>> {
+        let words = query.split_whitespace().collect::<Vec<&str>>();
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+        let filter = vec![
+            "(Title like ? escape '\\' or Artist like ? escape '\\' \
+             or Album like ? escape '\\' or Path like ? escape '\\')";
+            words.len()
+        ]
+        .join(" and ");
+        let sql = format!(
+            "select * from Library where {} order by Artist, Album, Title limit {}",
+            filter, limit
+        );
+
+        let mut query = sqlx::query(&sql);
+        for w in words {
+            let pattern = format!("%{}%", escape_like(w));
+            for _ in 0..4 {
+                query = query.bind(pattern.clone());
+            }
+        }
+        let mut conn = self.connection.lock().await;
+        let rows = query.fetch_all(&mut *conn).await?;
+
+        rows.iter().map(Track::from_row).collect()
+    }
+
+    /// Indexes new and changed files and forgets the ones that are gone.
+    /// `None` if a scan is already running.
+    pub async fn scan(&self, config: &LibraryConfig) -> DbResult<Option<ScanStats>> {
+        if self.scanning.swap(true, Ordering::SeqCst) {
+            return Ok(None);
+        }
+        let res = self.scan_dirs(config).await;
+        self.scanning.store(false, Ordering::SeqCst);
+        res.map(Some)
+    }
+
+    async fn scan_dirs(&self, config: &LibraryConfig) -> DbResult<ScanStats> {
+        let dirs = config.dirs.clone();
+        let files = tokio::task::spawn_blocking(move || walk(&dirs))
+            .await
+            .unwrap_or_default();
+
+        let known = {
+            let mut conn = self.connection.lock().await;
+            let rows = sqlx::query("select Path, Modified, Cover from Library")
+                .fetch_all(&mut *conn)
+                .await?;
+            rows.iter()
+                .map(|r| {
+                    Ok((
+                        r.try_get::<String, _>("Path")?,
+                        (
+                            r.try_get::<i64, _>("Modified")?,
+                            r.try_get::<Option<String>, _>("Cover")?,
+                        ),
+                    ))
+                })
+                .collect::<DbResult<HashMap<_, _>>>()?
+        };
+        let mut stats = ScanStats::default();
+
+        for (path, (_, cover)) in known.iter().filter(|(p, _)| !files.contains_key(*p)) {
+            let mut conn = self.connection.lock().await;
+            sqlx::query("delete from Library where Path = ?")
+                .bind(path.clone())
+                .execute(&mut *conn)
+                .await?;
+            if let Some(c) = cover {
+                let _ = tokio::fs::remove_file(config.covers.join(c)).await;
+            }
+            stats.removed += 1;
+        }
+
+        for (path, modified) in &files {
+            let old = known.get(path);
+            if old.map(|o| o.0) == Some(*modified) {
+                continue;
+            }
+            let tags = match probe(path, &config.covers).await {
+                Ok(t) => t,
+                Err(e) => {
+                    debug!("Skipping {}: {}", path, e);
+                    continue;
+                }
+            };
+
+            let sql = if old.is_some() {
+                stats.updated += 1;
+                "update Library set Title = ?, Artist = ?, Album = ?, Duration = ?, Cover = ?,
+                    Modified = ? where Path = ?"
+            } else {
+                stats.added += 1;
+                "insert into Library (Title, Artist, Album, Duration, Cover, Modified, Path)
+                    values (?, ?, ?, ?, ?, ?, ?)"
+            };
+            let mut conn = self.connection.lock().await;
+            sqlx::query(sql)
+                .bind(tags.title)
+                .bind(tags.artist)
+                .bind(tags.album)
+                .bind(tags.duration.map(|d| d.as_millis() as i64))
+                .bind(tags.cover)
+                .bind(*modified)
+                .bind(path.clone())
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        info!(
+            "Library scanned: {} added, {} updated, {} removed",
+            stats.added, stats.updated, stats.removed
+        );
+        Ok(stats)
+    }
+}
+
+/// Makes `%` and `_` in a search word match themselves
+fn escape_like(word: &str) -> String {
+    word.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Audio files under `dirs` with their modification time.
+/// Paths that aren't UTF-8 can't be stored and are left out.
+fn walk(dirs: &[PathBuf]) -> HashMap<String, i64> {
+    let mut files = HashMap::new();
+    let mut pending = dirs.to_vec();
+
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(e) => e,
+            Err(e) => {
+                warn!("Couldn't read {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            match entry.file_type() {
+                // Linked directories aren't followed, they could loop
+                Ok(t) if t.is_dir() => pending.push(path),
+                Ok(_) if is_audio(&path) => {
+                    let modified = fs::metadata(&path)
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|m| m.duration_since(UNIX_EPOCH).ok());
+                    if let (Some(p), Some(m)) = (path.to_str(), modified) {
+                        files.insert(p.to_owned(), m.as_secs() as i64);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    files
+}
+
+fn is_audio(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map_or(false, |e| EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+#[derive(Debug, Default)]
+struct Tags {
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    duration: Option<Duration>,
+    cover: Option<String>,
+}
+
+/// Reads the tags with ffprobe, extracting the embedded cover to `covers`
+async fn probe(path: &str, covers: &Path) -> Result<Tags, Box<dyn Error + Send + Sync>> {
+    let output = Command::new("ffprobe")
+        .args(&[
+            "-v",
+            "quiet",
+            "-of",
+            "json",
+            "-show_format",
+            "-show_streams",
+            path,
+        ])
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err("not readable by ffprobe".into());
+    }
+    let value: Value = serde_json::from_slice(&output.stdout)?;
+    let streams = value
+        .get("streams")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    // Ogg files keep their tags on the stream, and the case of the keys varies
+    let tag = |key: &str| {
+        std::iter::once(&value["format"])
+            .chain(streams)
+            .filter_map(|v| v.get("tags").and_then(Value::as_object))
+            .flat_map(|t| t.iter())
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .and_then(|(_, v)| v.as_str())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_owned)
+    };
+
+    let cover = streams
+        .iter()
+        .find(|s| s["disposition"]["attached_pic"] == 1);
+    let cover = match cover {
+        Some(s) => extract_cover(path, s, covers).await,
+        None => None,
+    };
+
+    Ok(Tags {
+        title: tag("title"),
+        artist: tag("artist").or_else(|| tag("album_artist")),
+        album: tag("album"),
+        duration: value["format"]["duration"]
+            .as_str()
+            .and_then(|d| d.parse::<f64>().ok())
+            .map(Duration::from_secs_f64),
+        cover,
+    })
+}
+
+/// Copies the picture stream to a file named after the track's path
+async fn extract_cover(path: &str, stream: &Value, covers: &Path) -> Option<String> {
+    let ext = match stream["codec_name"].as_str()? {
+        "mjpeg" => "jpg",
+        "png" => "png",
+        _ => return None,
+    };
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    let name = format!("{:016x}.{}", hasher.finish(), ext);
+
+    if let Err(e) = tokio::fs::create_dir_all(covers).await {
+        warn!("Couldn't create {}: {}", covers.display(), e);
+        return None;
+    }
+    let status = Command::new("ffmpeg")
+        .args(&["-v", "quiet", "-y", "-i", path, "-map"])
+        .arg(format!("0:{}", stream["index"].as_u64()?))
+        .args(&["-c", "copy", "-f", "image2"])
+        .arg(covers.join(&name))
+        .kill_on_drop(true)
+        .status()
+        .await
+        .ok()?;
+    if status.success() {
+        Some(name)
+    } else {
+        None
+    }
+}
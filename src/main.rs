@@ -1,27 +1,88 @@
+use attachment::AttachmentConfig;
 use commands::*;
+use directory::{Directory, DirectoryConfig};
+use icecast::IcecastConfig;
+use icy::StreamConfig;
+use rebroadcast::{RebroadcastConfig, Rebroadcasts};
+use record::{RecordConfig, Recordings};
+use server::ServerConfig;
 use serde::Deserialize;
 use serenity::{
     async_trait,
     client::{bridge::gateway::ShardManager, Client, Context, EventHandler},
     framework::{standard::macros::group, StandardFramework},
-    model::gateway::{Activity, Ready},
+    model::{
+        gateway::{Activity, Ready},
+        id::GuildId,
+    },
     prelude::TypeMapKey,
 };
-use songbird::SerenityInit;
+use songbird::{SerenityInit, Songbird};
 use std::{collections::HashMap, env, fs, path::PathBuf, sync::Arc};
+use tap::Taps;
 use tokio::sync::Mutex;
 use tracing::warn;
+use ytdl::PlaylistConfig;
 
 #[cfg(feature = "cache")]
 mod cache;
 
+mod attachment;
+mod auth;
 mod commands;
+mod date;
+mod directory;
+mod export;
 mod icecast;
+mod icy;
+mod lazy;
+#[cfg(feature = "cache")]
+mod library;
+mod playlist;
+#[cfg(feature = "cache")]
+mod presets;
+mod range;
+mod rebroadcast;
+mod record;
+#[cfg(feature = "cache")]
+mod secrets;
+mod server;
+mod shoutcast;
+mod sniff;
+mod station;
+mod tap;
+mod ytdl;
+
+/// Presets, saved credentials and the music library
+#[cfg(feature = "cache")]
+const DATABASE: &str = "sqlite://sodmb.db?mode=rwc";
+/// Where the presets were kept before the database was shared
+#[cfg(feature = "cache")]
+const OLD_DATABASE: &str = "presets.db";
 
 #[derive(Deserialize)]
 struct Config {
     token: String,
     prefix: String,
+    #[serde(default)]
+    icecast: IcecastConfig,
+    #[serde(default)]
+    stream: StreamConfig,
+    #[serde(default)]
+    record: RecordConfig,
+    #[serde(default)]
+    rebroadcast: RebroadcastConfig,
+    #[serde(default)]
+    server: ServerConfig,
+    #[serde(default)]
+    directory: DirectoryConfig,
+    #[serde(default)]
+    playlist: PlaylistConfig,
+    #[serde(default)]
+    attachments: AttachmentConfig,
+    #[cfg(feature = "cache")]
+    #[serde(default)]
+    library: library::LibraryConfig,
 }
 
 struct Handler {
@@ -34,18 +95,23 @@ struct Misc;
 
 #[group]
 #[commands(
-    add, raw, icecast, pause, play, skip, clear, queue, pop, leave, join, np
+    add, raw, icecast, pause, play, skip, clear, queue, pop, leave, join, np, export,
+    history, record, rebroadcast, listen, preset, radio, secret, search, lib, playnext,
+    insert, restore
 )]
 struct Music;
 
 struct ShardManagerContainer;
 struct CommandCounter;
+/// What was queued in each guild when the bot left, for `restore`
+struct SavedQueues;
 
 #[derive(Clone)]
 struct QueueEntry {
     url: String,
     file: Option<PathBuf>,
     owner: u64,
+    range: Option<range::Range>,
 }
 
 impl TypeMapKey for ShardManagerContainer {
@@ -56,6 +122,10 @@ impl TypeMapKey for CommandCounter {
     type Value = HashMap<String, u64>;
 }
 
+impl TypeMapKey for SavedQueues {
+    type Value = HashMap<GuildId, Vec<QueueEntry>>;
+}
+
 #[cfg(feature = "cache")]
 use cache::TrackCache;
 #[cfg(feature = "cache")]
@@ -76,6 +146,11 @@ impl EventHandler for Handler {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt::init();
 
+    let mut args = env::args().skip(1);
+    if let Some("export") = args.next().as_deref() {
+        return export::cli(args);
+    }
+
     let config = read_config()?;
 
     let framework = StandardFramework::new()
@@ -86,19 +161,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .after(after)
         .help(&HELP);
 
+    let songbird = Songbird::serenity();
+    let taps = Arc::new(Taps::default());
+
     let mut client = Client::builder(config.token)
         .event_handler(Handler {
             prefix: config.prefix,
         })
         .framework(framework)
-        .register_songbird()
+        .register_songbird_with(songbird.clone())
         .await
         .unwrap();
 
     {
         let mut data = client.data.write().await;
         data.insert::<CommandCounter>(HashMap::default());
+        data.insert::<SavedQueues>(HashMap::default());
         data.insert::<ShardManagerContainer>(Arc::clone(&client.shard_manager));
+        data.insert::<IcecastConfig>(config.icecast);
+        data.insert::<StreamConfig>(config.stream);
+        data.insert::<RecordConfig>(config.record);
+        data.insert::<Recordings>(Arc::default());
+        data.insert::<RebroadcastConfig>(config.rebroadcast);
+        data.insert::<Rebroadcasts>(Arc::default());
+        data.insert::<Taps>(taps.clone());
+        data.insert::<ServerConfig>(config.server.clone());
+        data.insert::<DirectoryConfig>(config.directory);
+        data.insert::<Directory>(Arc::default());
+        data.insert::<PlaylistConfig>(config.playlist);
+        // Nothing's queued yet, whatever is there was left by the last run
+        attachment::clear(&config.attachments.dir).await;
+        data.insert::<AttachmentConfig>(config.attachments);
 
         #[cfg(feature = "cache")]
         match TrackCache::new("sqlite://audio_cache/cache.db").await {
@@ -109,8 +202,46 @@ Cache will be disabled",
                 e
             ),
         }
+        #[cfg(feature = "cache")]
+        if fs::metadata(OLD_DATABASE).is_ok() && fs::metadata("sodmb.db").is_err() {
+            match fs::rename(OLD_DATABASE, "sodmb.db") {
+                Ok(()) => tracing::info!("Moved {} to sodmb.db", OLD_DATABASE),
+                Err(e) => warn!("Couldn't move {} to sodmb.db: {}", OLD_DATABASE, e),
+            }
+        }
+        #[cfg(feature = "cache")]
+        match presets::Presets::new(DATABASE).await {
+            Ok(p) => data.insert::<presets::Presets>(p),
+            Err(e) => tracing::error!("Couldn't open the preset database: {}", e),
+        }
+        #[cfg(feature = "cache")]
+        match secrets::Secrets::new(DATABASE).await {
+            Ok(s) => data.insert::<secrets::Secrets>(s),
+            Err(e) => tracing::error!("Couldn't open the credential database: {}", e),
+        }
+        #[cfg(feature = "cache")]
+        match library::Library::new(DATABASE).await {
+            Ok(l) => {
+                let (library, config) = (l.clone(), config.library.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = library.scan(&config).await {
+                        warn!("Error scanning the music library: {}", e);
+                    }
+                });
+                data.insert::<library::Library>(l);
+            }
+            Err(e) => tracing::error!("Couldn't open the library database: {}", e),
+        }
+        #[cfg(feature = "cache")]
+        data.insert::<library::LibraryConfig>(config.library.clone());
     }
 
+    #[cfg(feature = "cache")]
+    let covers = Some(config.library.covers);
+    #[cfg(not(feature = "cache"))]
+    let covers = None;
+    tokio::spawn(server::serve(config.server, taps, songbird, covers));
+
     let shard_manager = client.shard_manager.clone();
 
     tokio::spawn(async move {
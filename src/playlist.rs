@@ -0,0 +1,377 @@
+use reqwest::Url;
+use std::error::Error;
+use tracing::debug;
+
+/// Playlists bigger than this aren't playlists
+const MAX_SIZE: usize = 1024 * 1024;
+/// Protocols ffmpeg may open, local files aren't among them
+pub const PROTOCOLS: &[&str] = &["http", "rtmp", "ftp", "hls", "tcp", "udp"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub url: String,
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    M3u,
+    Pls,
+    Xspf,
+    Asx,
+}
+
+impl Format {
+    pub fn from_url(url: &str) -> Option<Self> {
+        let path = url.split(|c| c == '?' || c == '#').next()?;
+        let ext = path.rsplit('.').next()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "m3u" | "m3u8" => Some(Format::M3u),
+            "pls" => Some(Format::Pls),
+            "xspf" => Some(Format::Xspf),
+            "asx" | "wax" | "wvx" => Some(Format::Asx),
+            _ => None,
+        }
+    }
+
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next()?.trim().to_ascii_lowercase();
+        match mime.as_str() {
+            "audio/x-mpegurl"
+            | "audio/mpegurl"
+            | "application/x-mpegurl"
+            | "application/vnd.apple.mpegurl" => Some(Format::M3u),
+            "audio/x-scpls" | "application/pls+xml" => Some(Format::Pls),
+            "application/xspf+xml" => Some(Format::Xspf),
+            "video/x-ms-asf" | "video/x-ms-asx" | "audio/x-ms-wax" | "video/x-ms-wvx" => {
+                Some(Format::Asx)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn parse(self, body: &str) -> Vec<Entry> {
+        match self {
+            Format::M3u => parse_m3u(body),
+            Format::Pls => parse_pls(body),
+            Format::Xspf => parse_xspf(body),
+            Format::Asx => parse_asx(body),
+        }
+    }
+}
+
+/// Lines that aren't comments, with titles from `#EXTINF:duration,title`
+pub fn parse_m3u(body: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut title = None;
+    for line in body.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            title = info
+                .splitn(2, ',')
+                .nth(1)
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .map(str::to_owned);
+        } else if !line.is_empty() && !line.starts_with('#') {
+            entries.push(Entry {
+                url: line.to_owned(),
+                title: title.take(),
+            });
+        }
+    }
+    entries
+}
+
+/// INI-like, `FileN=` and `TitleN=` under `[playlist]`
+pub fn parse_pls(body: &str) -> Vec<Entry> {
+    let mut files = Vec::new();
+    let mut titles = Vec::new();
+    for line in body.lines().map(str::trim) {
+        let (key, value) = match line.find('=') {
+            Some(i) => (line[..i].trim().to_ascii_lowercase(), line[i + 1..].trim()),
+            None => continue,
+        };
+        if let Some(n) = key.strip_prefix("file").and_then(|n| n.parse::<u32>().ok()) {
+            files.push((n, value.to_owned()));
+        } else if let Some(n) = key
+            .strip_prefix("title")
+            .and_then(|n| n.parse::<u32>().ok())
+        {
+            titles.push((n, value.to_owned()));
+        }
+    }
+    files.sort_by_key(|(n, _)| *n);
+
+    files
+        .into_iter()
+        .map(|(n, url)| Entry {
+            url,
+            title: titles
+                .iter()
+                .find(|(t, _)| *t == n)
+                .map(|(_, t)| t.clone())
+                .filter(|t| !t.is_empty()),
+        })
+        .collect()
+}
+
+/// `<track><location/><title/><creator/></track>` in `<trackList>`
+pub fn parse_xspf(body: &str) -> Vec<Entry> {
+    let doc = match roxmltree::Document::parse(body) {
+        Ok(d) => d,
+        Err(e) => {
+            debug!("Invalid XSPF: {}", e);
+            return Vec::new();
+        }
+    };
+    let child_text = |node: roxmltree::Node, name| {
+        node.children()
+            .find(|c| c.has_tag_name(name))
+            .and_then(|c| c.text())
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(str::to_owned)
+    };
+
+    doc.descendants()
+        .filter(|n| n.has_tag_name("track"))
+        .filter_map(|track| {
+            let url = child_text(track, "location")?;
+            let title = match (child_text(track, "creator"), child_text(track, "title")) {
+                (Some(c), Some(t)) => Some(format!("{} - {}", c, t)),
+                (_, t) => t,
+            };
+            Some(Entry { url, title })
+        })
+        .collect()
+}
+
+/// ASX files are rarely valid XML and tag case varies, so they're scanned by hand
+/// for `<entry>` blocks with `<ref href="..."/>` and `<title>`
+pub fn parse_asx(body: &str) -> Vec<Entry> {
+    // ASCII lowercasing keeps byte offsets the same
+    let lower = body.to_ascii_lowercase();
+    let mut entries = Vec::new();
+
+    let mut pos = 0;
+    while let Some(start) = lower[pos..].find("<entry").map(|i| i + pos) {
+        let end = lower[start..]
+            .find("</entry>")
+            .map(|i| i + start)
+            .unwrap_or_else(|| lower.len());
+        let (block, block_lower) = (&body[start..end], &lower[start..end]);
+
+        let title = block_lower.find("<title>").and_then(|t| {
+            let t = t + "<title>".len();
+            let e = block_lower[t..].find("</title>")? + t;
+            Some(block[t..e].trim().to_owned()).filter(|t| !t.is_empty())
+        });
+
+        // Other refs are fallbacks for the same entry
+        if let Some(url) = block_lower
+            .match_indices("<ref")
+            .find_map(|(r, _)| attribute(&block[r..], &block_lower[r..], "href"))
+        {
+            entries.push(Entry { url, title });
+        }
+        pos = end;
+    }
+    entries
+}
+
+/// Value of a quoted attribute in the tag at the start of `tag`
+fn attribute(tag: &str, tag_lower: &str, name: &str) -> Option<String> {
+    let close = tag_lower.find('>')?;
+    let start = tag_lower[..close].find(&format!("{}=", name))? + name.len() + 1;
+    let quote = tag[start..].chars().next()?;
+    if quote != '"' && quote != '\'' {
+        return None;
+    }
+    let end = tag[start + 1..].find(quote)? + start + 1;
+    Some(tag[start + 1..end].replace("&amp;", "&"))
+}
+
+/// HLS playlists are streams themselves, ffmpeg plays them directly
+fn is_hls(body: &str) -> bool {
+    body.contains("#EXT-X-")
+}
+
+/// Fetches the URL if it looks like a playlist by extension or Content-Type and
+/// returns its entries, with relative URLs made absolute. `None` if it's not a playlist.
+pub async fn resolve(url: &str) -> Result<Option<Vec<Entry>>, Box<dyn Error + Send + Sync>> {
+    let base = Url::parse(url)?;
+    let by_extension = Format::from_url(base.path());
+
+    let mut response = reqwest::get(base.clone()).await?.error_for_status()?;
+    let format = match by_extension.or_else(|| {
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|c| c.to_str().ok())
+            .and_then(Format::from_content_type)
+    }) {
+        Some(f) => f,
+        // Dropping the response closes the connection, in case it's the audio itself
+        None => return Ok(None),
+    };
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() > MAX_SIZE {
+            return Ok(None);
+        }
+    }
+    let body = String::from_utf8_lossy(&body);
+    if format == Format::M3u && is_hls(&body) {
+        return Ok(None);
+    }
+
+    let entries = remote_entries(&base, format.parse(&body));
+    debug!("{} entries in playlist {}", entries.len(), url);
+
+    if entries.is_empty() {
+        Err("Empty playlist".into())
+    } else {
+        Ok(Some(entries))
+    }
+}
+
+/// Entries with relative URLs made absolute, leaving out the ones ffmpeg shouldn't
+/// open. A playlist on any server could point at local files otherwise.
+fn remote_entries(base: &Url, entries: Vec<Entry>) -> Vec<Entry> {
+    entries
+        .into_iter()
+        .filter_map(|e| {
+            let url = base.join(&e.url).ok()?;
+            if !PROTOCOLS.iter().any(|p| url.scheme().starts_with(p)) {
+                debug!("Skipping playlist entry {}", url);
+                return None;
+            }
+            Some(Entry {
+                url: url.to_string(),
+                title: e.title,
+            })
+        })
+        .collect()
+}
+
+/// First entry whose server answers, for radio playlists listing mirrors
+pub async fn first_working(entries: &[Entry]) -> Option<&Entry> {
+    for e in entries {
+        match reqwest::get(&e.url)
+            .await
+            .and_then(|r| r.error_for_status())
+        {
+            Ok(_) => return Some(e),
+            Err(err) => debug!("Skipping playlist entry {}: {}", e.url, err),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(url: &str, title: Option<&str>) -> Entry {
+        Entry {
+            url: url.to_owned(),
+            title: title.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn m3u() {
+        let body = include_str!("../tests/fixtures/playlists/radio.m3u");
+        assert_eq!(
+            parse_m3u(body),
+            vec![
+                entry(
+                    "http://radio.example.com:8000/live.mp3",
+                    Some("Example Radio")
+                ),
+                entry("http://backup.example.com/live.mp3", None),
+                entry("relative/track.ogg", Some("Artist - Song")),
+            ]
+        );
+    }
+
+    #[test]
+    fn hls_is_not_a_playlist() {
+        let body = include_str!("../tests/fixtures/playlists/stream.m3u8");
+        assert!(is_hls(body));
+        assert!(!is_hls(include_str!(
+            "../tests/fixtures/playlists/radio.m3u"
+        )));
+    }
+
+    #[test]
+    fn pls() {
+        let body = include_str!("../tests/fixtures/playlists/radio.pls");
+        assert_eq!(
+            parse_pls(body),
+            vec![
+                entry(
+                    "http://radio.example.com:8000/stream",
+                    Some("Example Radio (main)")
+                ),
+                entry("http://radio.example.com:8002/stream", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn xspf() {
+        let body = include_str!("../tests/fixtures/playlists/album.xspf");
+        assert_eq!(
+            parse_xspf(body),
+            vec![
+                entry("http://example.com/01.ogg", Some("Some Band - First")),
+                entry("http://example.com/02.ogg", Some("Second")),
+            ]
+        );
+    }
+
+    #[test]
+    fn asx() {
+        let body = include_str!("../tests/fixtures/playlists/radio.asx");
+        assert_eq!(
+            parse_asx(body),
+            vec![
+                entry(
+                    "http://radio.example.com/live.wma?a=1&b=2",
+                    Some("Example Radio")
+                ),
+                entry("mms://radio.example.com/backup", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn local_files_are_left_out() {
+        let base = Url::parse("http://radio.example.com/lists/radio.m3u").unwrap();
+        let body = include_str!("../tests/fixtures/playlists/local.m3u");
+        assert_eq!(
+            remote_entries(&base, parse_m3u(body)),
+            vec![
+                entry("http://radio.example.com/lists/relative.mp3", None),
+                entry("https://radio.example.com/live", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn detection() {
+        assert_eq!(
+            Format::from_url("http://a/b/list.PLS?x=1"),
+            Some(Format::Pls)
+        );
+        assert_eq!(Format::from_url("http://a/b/stream.mp3"), None);
+        assert_eq!(
+            Format::from_content_type("audio/x-mpegurl; charset=utf-8"),
+            Some(Format::M3u)
+        );
+        assert_eq!(Format::from_content_type("audio/mpeg"), None);
+    }
+}
@@ -0,0 +1,147 @@
+use serenity::{model::id::GuildId, prelude::TypeMapKey};
+use sqlx::{any::AnyConnection, Connection, Executor, Row};
+use std::{fmt, str::FromStr, sync::Arc};
+use tokio::sync::Mutex;
+
+type DbResult<T> = Result<T, sqlx::Error>;
+
+/// Which command a preset is played with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Icecast,
+    Raw,
+}
+
+impl Kind {
+    /// Name of the resolver the preset's URL is played with
+    pub fn resolver(self) -> &'static str {
+        match self {
+            Kind::Icecast => "icecast",
+            Kind::Raw => "ffmpeg",
+        }
+    }
+}
+
+impl FromStr for Kind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "icecast" | "shoutcast" => Ok(Kind::Icecast),
+            "raw" => Ok(Kind::Raw),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Icecast => "icecast",
+            Kind::Raw => "raw",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Preset {
+    pub name: String,
+    pub url: String,
+    pub kind: Kind,
+}
+
+/// Stations saved by each guild
+#[derive(Debug, Clone)]
+pub struct Presets {
+    connection: Arc<Mutex<AnyConnection>>,
+}
+
+impl TypeMapKey for Presets {
+    type Value = Presets;
+}
+
+impl Presets {
+    pub async fn new(uri: &str) -> DbResult<Presets> {
+        let mut conn = AnyConnection::connect(uri).await?;
+        conn.execute(
+            "
+create table if not exists Presets (
+    Guild text not null,
+    Name text not null,
+    Url text not null,
+    Kind text not null,
+    primary key (Guild, Name)
+)
+            ",
+        )
+        .await?;
+        Ok(Presets {
+            connection: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub async fn get(&self, guild: GuildId, name: &str) -> DbResult<Option<Preset>> {
+        let mut conn = self.connection.lock().await;
+
+        let row = sqlx::query("select Name, Url, Kind from Presets where Guild = ? and Name = ?")
+            .bind(guild.0.to_string())
+            .bind(name.to_lowercase())
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        row.map(|r| preset(&r)).transpose()
+    }
+
+    /// The guild's presets, sorted by name
+    pub async fn list(&self, guild: GuildId) -> DbResult<Vec<Preset>> {
+        let mut conn = self.connection.lock().await;
+
+        let rows = sqlx::query("select Name, Url, Kind from Presets where Guild = ? order by Name")
+            .bind(guild.0.to_string())
+            .fetch_all(&mut *conn)
+            .await?;
+
+        rows.iter().map(preset).collect()
+    }
+
+    /// Saves the preset, replacing the one with the same name
+    pub async fn insert(&self, guild: GuildId, preset: &Preset) -> DbResult<()> {
+        let mut conn = self.connection.lock().await;
+        let name = preset.name.to_lowercase();
+
+        sqlx::query("delete from Presets where Guild = ? and Name = ?")
+            .bind(guild.0.to_string())
+            .bind(name.clone())
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("insert into Presets values (?, ?, ?, ?)")
+            .bind(guild.0.to_string())
+            .bind(name)
+            .bind(preset.url.clone())
+            .bind(preset.kind.to_string())
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns false if there was no such preset
+    pub async fn remove(&self, guild: GuildId, name: &str) -> DbResult<bool> {
+        let mut conn = self.connection.lock().await;
+
+        let res = sqlx::query("delete from Presets where Guild = ? and Name = ?")
+            .bind(guild.0.to_string())
+            .bind(name.to_lowercase())
+            .execute(&mut *conn)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
+}
+
+fn preset(row: &sqlx::any::AnyRow) -> DbResult<Preset> {
+    let kind: String = row.try_get("Kind")?;
+    Ok(Preset {
+        name: row.try_get("Name")?,
+        url: row.try_get("Url")?,
+        kind: kind.parse().unwrap_or(Kind::Raw),
+    })
+}
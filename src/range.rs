@@ -0,0 +1,382 @@
+use songbird::input::{Codec, Container, Input, Reader};
+use std::{
+    fmt,
+    io::{self, Read},
+    time::Duration,
+};
+
+/// Bytes of float PCM per second of mono audio at 48kHz
+const MONO_RATE: usize = 48_000 * 4;
+/// Length of an Opus frame
+const FRAME_MILLIS: u128 = 20;
+
+/// Part of a track to play, from `start` until `end` or the end of the track
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Range {
+    pub start: Duration,
+    pub end: Option<Duration>,
+}
+
+impl Range {
+    /// `1:30`, `1:30-3:00` or `-3:00`, with hours and bare seconds allowed
+    pub fn parse(s: &str) -> Option<Range> {
+        let mut parts = s.splitn(2, '-');
+        let start = match parts.next()? {
+            "" => Duration::default(),
+            t => parse_time(t)?,
+        };
+        let end = match parts.next() {
+            Some(t) => Some(parse_time(t)?).filter(|e| *e > start),
+            None => None,
+        };
+        if s.contains('-') && end.is_none() {
+            return None;
+        }
+        Some(Range { start, end })
+    }
+
+    /// Takes the start time out of YouTube links like `?t=90` or `&t=1m30s`,
+    /// so the same video isn't cached once per offset
+    pub fn split_url(url: &str) -> (String, Option<Range>) {
+        if !url.contains("youtube.com/") && !url.contains("youtu.be/") {
+            return (url.to_owned(), None);
+        }
+        let (base, fragment) = match url.find('#') {
+            Some(i) => (&url[..i], &url[i + 1..]),
+            None => (url, ""),
+        };
+        let (path, query) = match base.find('?') {
+            Some(i) => (&base[..i], &base[i + 1..]),
+            None => (base, ""),
+        };
+
+        let offset = |p: &str| {
+            p.strip_prefix("t=")
+                .or_else(|| p.strip_prefix("start="))
+                .and_then(parse_offset)
+        };
+        let start = query
+            .split('&')
+            .chain(fragment.split('&'))
+            .filter_map(offset)
+            .last();
+        let start = match start {
+            Some(s) => s,
+            None => return (url.to_owned(), None),
+        };
+
+        let query = query
+            .split('&')
+            .filter(|p| !p.is_empty() && offset(p).is_none())
+            .collect::<Vec<&str>>()
+            .join("&");
+        let url = if query.is_empty() {
+            path.to_owned()
+        } else {
+            format!("{}?{}", path, query)
+        };
+        (url, Some(Range { start, end: None }))
+    }
+
+    /// How long the range plays for a track of `duration`
+    pub fn length(&self, duration: Duration) -> Duration {
+        self.end
+            .unwrap_or(duration)
+            .min(duration)
+            .checked_sub(self.start)
+            .unwrap_or_default()
+    }
+
+    /// Plays only the range of the input. The skipped part is seeked over when the
+    /// source allows it, read through otherwise. Opus is cut on frame boundaries and
+    /// stays Opus, so it can still be passed through.
+    pub fn wrap(self, mut input: Input) -> Input {
+        let channels = if input.stereo { 2 } else { 1 };
+        if let Some(d) = input.metadata.duration {
+            input.metadata.duration = Some(self.length(d));
+        }
+        if let (Codec::Opus(_), Container::Dca { .. }) = (&input.kind, &input.container) {
+            let reader = std::mem::replace(&mut input.reader, Reader::from(Vec::new()));
+            input.reader = Reader::Extension(Box::new(self.frames(reader)));
+            // The header was read when the input was opened
+            input.container = Container::Dca { first_frame: 0 };
+            return input;
+        }
+        let metadata = input.metadata.clone();
+        let stereo = input.stereo;
+        let reader = RangeReader {
+            skip: Some(self.start),
+            left: self
+                .end
+                .map(|e| bytes(e.checked_sub(self.start).unwrap_or_default(), channels)),
+            channels,
+            input,
+        };
+        Input::new(
+            stereo,
+            Reader::Extension(Box::new(reader)),
+            Codec::FloatPcm,
+            Container::Raw,
+            Some(*metadata),
+        )
+    }
+
+    /// Takes the frames of the range from a reader of DCA frames
+    fn frames<R: Read>(&self, inner: R) -> FrameReader<R> {
+        let frames = |d: Duration| (d.as_millis() / FRAME_MILLIS) as usize;
+        FrameReader {
+            inner,
+            skip: frames(self.start),
+            left: self
+                .end
+                .map(|e| frames(e.checked_sub(self.start).unwrap_or_default())),
+            frame: Vec::new(),
+            sent: 0,
+        }
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format_time(self.start))?;
+        if let Some(e) = self.end {
+            write!(f, "-{}", format_time(e))?;
+        }
+        Ok(())
+    }
+}
+
+/// `1:02:03`, `1:30` or `90`
+fn parse_time(s: &str) -> Option<Duration> {
+    let mut secs = 0.0;
+    for part in s.split(':') {
+        let n = part.parse::<f64>().ok().filter(|n| *n >= 0.0 && *n < 1e7)?;
+        secs = secs * 60.0 + n;
+    }
+    if s.split(':').count() > 3 {
+        return None;
+    }
+    Some(Duration::from_secs_f64(secs))
+}
+
+/// `90`, `90s` or `1h2m30s`, like YouTube's `t=`
+fn parse_offset(s: &str) -> Option<Duration> {
+    if let Ok(n) = s.parse::<u64>() {
+        return Some(Duration::from_secs(n));
+    }
+    let mut secs = 0;
+    let mut number = String::new();
+    for c in s.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'h' | 'm' | 's' => {
+                let n = number.parse::<u64>().ok()?;
+                let unit = match c {
+                    'h' => 3600,
+                    'm' => 60,
+                    _ => 1,
+                };
+                secs = n.saturating_mul(unit).saturating_add(secs);
+                number.clear();
+            }
+            _ => return None,
+        }
+    }
+    if number.is_empty() && !s.is_empty() {
+        Some(Duration::from_secs(secs))
+    } else {
+        None
+    }
+}
+
+fn format_time(d: Duration) -> String {
+    let s = d.as_secs();
+    if s >= 3600 {
+        format!("{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+    } else {
+        format!("{}:{:02}", s / 60, s % 60)
+    }
+}
+
+fn bytes(d: Duration, channels: usize) -> usize {
+    (d.as_secs_f64() * (MONO_RATE * channels) as f64) as usize / 4 * 4
+}
+
+struct RangeReader {
+    input: Input,
+    /// Seeked to on the first read
+    skip: Option<Duration>,
+    /// Bytes until the end of the range
+    left: Option<usize>,
+    channels: usize,
+}
+
+impl Read for RangeReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if let Some(start) = self.skip.take() {
+            if start > Duration::default() && self.input.seek_time(start).is_none() {
+                let mut skip = bytes(start, self.channels);
+                let mut buf = [0u8; 4096];
+                while skip > 0 {
+                    let n = self.input.read(&mut buf[..skip.min(4096)])?;
+                    if n == 0 {
+                        return Ok(0);
+                    }
+                    skip -= n;
+                }
+            }
+        }
+
+        let len = self.left.map_or(out.len(), |l| l.min(out.len()));
+        if len == 0 {
+            return Ok(0);
+        }
+        let n = self.input.read(&mut out[..len])?;
+        if let Some(l) = &mut self.left {
+            *l -= n;
+        }
+        Ok(n)
+    }
+}
+
+/// Passes on whole DCA frames, an `i16` length and an Opus packet each, from the
+/// start of the range until its end
+struct FrameReader<R> {
+    inner: R,
+    /// Frames before the range starts
+    skip: usize,
+    /// Frames until the end of the range
+    left: Option<usize>,
+    /// The frame being passed on
+    frame: Vec<u8>,
+    /// Bytes of `frame` already read
+    sent: usize,
+}
+
+impl<R: Read> FrameReader<R> {
+    /// Reads the next frame into `frame`, false at the end of the input
+    fn next_frame(&mut self) -> io::Result<bool> {
+        let mut len = [0u8; 2];
+        let read = self.inner.read_exact(&mut len).and_then(|_| {
+            let len = i16::from_le_bytes(len).max(0) as usize;
+            self.frame.clear();
+            self.frame.extend_from_slice(&(len as i16).to_le_bytes());
+            self.frame.resize(2 + len, 0);
+            self.inner.read_exact(&mut self.frame[2..])
+        });
+        self.sent = 0;
+        match read {
+            Ok(()) => Ok(true),
+            // Cut off in the middle of a frame
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.frame.clear();
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<R: Read> Read for FrameReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.skip > 0 {
+            if !self.next_frame()? {
+                return Ok(0);
+            }
+            self.sent = self.frame.len();
+            self.skip -= 1;
+        }
+
+        if self.sent == self.frame.len() {
+            if self.left == Some(0) || !self.next_frame()? {
+                return Ok(0);
+            }
+            if let Some(l) = &mut self.left {
+                *l -= 1;
+            }
+        }
+        let n = (self.frame.len() - self.sent).min(out.len());
+        out[..n].copy_from_slice(&self.frame[self.sent..self.sent + n]);
+        self.sent += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(text: &str) -> impl Iterator<Item = Vec<&str>> {
+        text.lines()
+            .filter(|l| !l.starts_with('#'))
+            .map(|l| l.split(" | ").map(str::trim).collect())
+    }
+
+    #[test]
+    fn ranges() {
+        for parts in fixture(include_str!("../tests/fixtures/ranges.txt")) {
+            let shown = Range::parse(parts[0]).map(|r| r.to_string());
+            assert_eq!(shown.as_deref().unwrap_or("none"), parts[1], "{}", parts[0]);
+        }
+    }
+
+    #[test]
+    fn youtube_links() {
+        for parts in fixture(include_str!("../tests/fixtures/youtube_links.txt")) {
+            let (url, range) = Range::split_url(parts[0]);
+            assert_eq!(url, parts[1]);
+            let start = range.map(|r| {
+                assert_eq!(r.end, None);
+                r.start.as_secs().to_string()
+            });
+            assert_eq!(start.as_deref().unwrap_or("none"), parts[2], "{}", parts[0]);
+        }
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(parse_offset("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_offset("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_offset("1h2m30s"), Some(Duration::from_secs(3750)));
+        assert_eq!(parse_offset("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_offset("1m30"), None);
+        assert_eq!(parse_offset("h"), None);
+        assert_eq!(parse_offset(""), None);
+    }
+
+    #[test]
+    fn opus_frames() {
+        // Frame `n` is `n` bytes of `n`, 20ms each
+        let frame = |n: u8| {
+            let mut f = (n as i16).to_le_bytes().to_vec();
+            f.resize(2 + n as usize, n);
+            f
+        };
+        let dca = (1..=10).flat_map(frame).collect::<Vec<u8>>();
+        let cut = |dca: &[u8], start, end: Option<u64>| {
+            let range = Range {
+                start: Duration::from_millis(start),
+                end: end.map(Duration::from_millis),
+            };
+            let mut reader = range.frames(dca);
+            // Small reads split the frames
+            let (mut out, mut buf) = (Vec::new(), [0u8; 3]);
+            loop {
+                let n = reader.read(&mut buf).unwrap();
+                if n == 0 {
+                    return out;
+                }
+                out.extend_from_slice(&buf[..n]);
+            }
+        };
+
+        assert_eq!(cut(&dca, 60, Some(100)), [frame(4), frame(5)].concat());
+        assert_eq!(cut(&dca, 170, None), [frame(9), frame(10)].concat());
+        assert_eq!(cut(&dca, 0, Some(20)), frame(1));
+        assert_eq!(cut(&dca, 0, None), dca);
+        assert!(cut(&dca, 300, None).is_empty());
+        // Half a frame isn't passed on
+        let truncated = &dca[..dca.len() - 3];
+        assert_eq!(cut(truncated, 140, None), [frame(8), frame(9)].concat());
+    }
+}
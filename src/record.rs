@@ -0,0 +1,248 @@
+use crate::{export, tap::Packet};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serenity::{model::id::GuildId, prelude::TypeMapKey};
+use songbird::input::Metadata;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+    sync::{broadcast, oneshot},
+};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RecordConfig {
+    /// Recordings are saved in a subdirectory per guild
+    pub dir: PathBuf,
+    /// Seconds after which a recording stops by itself
+    pub max_duration: u64,
+    /// MiB after which a recording stops by itself
+    pub max_size: u64,
+}
+
+impl Default for RecordConfig {
+    fn default() -> Self {
+        Self {
+            dir: "recordings".into(),
+            max_duration: 3600,
+            max_size: 256,
+        }
+    }
+}
+
+impl TypeMapKey for RecordConfig {
+    type Value = RecordConfig;
+}
+
+impl RecordConfig {
+    fn guild_dir(&self, guild: GuildId) -> PathBuf {
+        self.dir.join(guild.0.to_string())
+    }
+
+    /// Recordings of a guild as (file name, size in bytes), newest first
+    pub fn list(&self, guild: GuildId) -> io::Result<Vec<(String, u64)>> {
+        let dir = self.guild_dir(guild);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut files = fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .filter_map(|e| {
+                let name = e.file_name().into_string().ok()?;
+                if !name.ends_with(".dca") {
+                    return None;
+                }
+                Some((name, e.metadata().ok()?.len()))
+            })
+            .collect::<Vec<_>>();
+        // Names are timestamps
+        files.sort_by(|a, b| b.0.cmp(&a.0));
+        Ok(files)
+    }
+
+    /// Path of one of the guild's recordings, `None` if there's no such file
+    pub fn path(&self, guild: GuildId, name: &str) -> Option<PathBuf> {
+        if name.contains(|c| c == '/' || c == '\\') || name.starts_with('.') {
+            return None;
+        }
+        let path = self.guild_dir(guild).join(name);
+        Some(path).filter(|p| p.is_file())
+    }
+}
+
+/// A recording in progress
+struct Recording {
+    path: PathBuf,
+    started: DateTime<Utc>,
+    stop: oneshot::Sender<()>,
+}
+
+/// Recordings in progress, at most one per guild
+#[derive(Default)]
+pub struct Recordings(Mutex<HashMap<GuildId, Recording>>);
+
+impl TypeMapKey for Recordings {
+    type Value = Arc<Recordings>;
+}
+
+impl Recordings {
+    /// Path and start time of the guild's recording
+    pub fn active(&self, guild: GuildId) -> Option<(PathBuf, DateTime<Utc>)> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&guild)
+            .map(|r| (r.path.clone(), r.started))
+    }
+
+    /// Stops the guild's recording, returning the path of the file
+    pub fn stop(&self, guild: GuildId) -> Option<PathBuf> {
+        let recording = self.0.lock().unwrap().remove(&guild)?;
+        let _ = recording.stop.send(());
+        Some(recording.path)
+    }
+
+    /// Writes the packets from `rx` to a new DCA file until stopped or over the limits
+    /// in `config`. `on_end` gets called with the reason when it stops by itself.
+    pub async fn start(
+        self: &Arc<Self>,
+        guild: GuildId,
+        mut rx: broadcast::Receiver<Packet>,
+        config: &RecordConfig,
+        duration: Duration,
+        meta: Metadata,
+        on_end: impl FnOnce(String) + Send + 'static,
+    ) -> io::Result<PathBuf> {
+        let started = Utc::now();
+        let dir = config.guild_dir(guild);
+        let path = dir.join(format!("{}.dca", started.format("%Y-%m-%d_%H-%M-%S")));
+
+        let (stop, mut stopped) = oneshot::channel();
+        {
+            let mut map = self.0.lock().unwrap();
+            if map.contains_key(&guild) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "Already recording",
+                ));
+            }
+            map.insert(
+                guild,
+                Recording {
+                    path: path.clone(),
+                    started,
+                    stop,
+                },
+            );
+        }
+
+        let file = async {
+            tokio::fs::create_dir_all(&dir).await?;
+            let file = BufWriter::new(File::create(&path).await?);
+            Ok::<_, io::Error>(file)
+        }
+        .await;
+        let mut file = match file {
+            Ok(f) => f,
+            Err(e) => {
+                self.0.lock().unwrap().remove(&guild);
+                return Err(e);
+            }
+        };
+
+        let max_size = config.max_size * 1024 * 1024;
+        let recordings = self.clone();
+        let task_path = path.clone();
+        tokio::spawn(async move {
+            let deadline = tokio::time::sleep(duration);
+            tokio::pin!(deadline);
+            let mut size = 0;
+            // Written with the first packet, which tells the channel count
+            let mut header = Some(meta);
+
+            let reason = loop {
+                let packet = tokio::select! {
+                    _ = &mut stopped => break None,
+                    _ = &mut deadline => break Some("time limit reached"),
+                    p = rx.recv() => p,
+                };
+                match packet {
+                    Ok(p) => {
+                        if let Err(e) = write_packet(&mut file, &mut header, &p).await {
+                            warn!("Error writing recording: {}", e);
+                            break Some("write error");
+                        }
+                        size += 2 + p.len() as u64;
+                        if size >= max_size {
+                            break Some("size limit reached");
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("Recording fell behind, skipped {} packets", n)
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break Some("audio source gone"),
+                }
+            };
+
+            // Still a valid file without any audio
+            if let Some(meta) = header {
+                if let Err(e) = file.write_all(&export::dca_header(2, &meta)).await {
+                    warn!("Error writing recording: {}", e);
+                }
+            }
+            if let Err(e) = file.flush().await {
+                warn!("Error writing recording: {}", e);
+            }
+            info!("Wrote {}KiB to {}", size / 1024, task_path.display());
+
+            if let Some(reason) = reason {
+                // Only remove the entry if it's still this recording
+                let mut map = recordings.0.lock().unwrap();
+                if map.get(&guild).map_or(false, |r| r.path == task_path) {
+                    map.remove(&guild);
+                }
+                drop(map);
+                on_end(format!(
+                    "Recording {} stopped: {}",
+                    file_name(&task_path),
+                    reason
+                ));
+            }
+        });
+
+        Ok(path)
+    }
+}
+
+/// Appends a packet, after the header if it's the first one
+async fn write_packet(
+    file: &mut BufWriter<File>,
+    header: &mut Option<Metadata>,
+    packet: &[u8],
+) -> io::Result<()> {
+    if let Some(meta) = header.take() {
+        let channels = export::packet_channels(packet);
+        file.write_all(&export::dca_header(channels, &meta)).await?;
+    }
+    write_frame(file, packet).await
+}
+
+/// Appends a DCA frame, the packet prefixed by its length
+async fn write_frame(file: &mut BufWriter<File>, packet: &[u8]) -> io::Result<()> {
+    file.write_all(&(packet.len() as i16).to_le_bytes()).await?;
+    file.write_all(packet).await
+}
+
+pub fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
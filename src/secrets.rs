@@ -0,0 +1,107 @@
+use crate::auth::{self, Credentials};
+use serenity::{model::id::GuildId, prelude::TypeMapKey};
+use sqlx::{any::AnyConnection, Connection, Executor, Row};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+type DbResult<T> = Result<T, sqlx::Error>;
+
+/// Credentials for private streams, saved by each guild.
+/// Stored for a host, or a host and mount point.
+#[derive(Debug, Clone)]
+pub struct Secrets {
+    connection: Arc<Mutex<AnyConnection>>,
+}
+
+impl TypeMapKey for Secrets {
+    type Value = Secrets;
+}
+
+impl Secrets {
+    pub async fn new(uri: &str) -> DbResult<Secrets> {
+        let mut conn = AnyConnection::connect(uri).await?;
+        conn.execute(
+            "
+create table if not exists Secrets (
+    Guild text not null,
+    Target text not null,
+    User text not null,
+    Password text not null,
+    primary key (Guild, Target)
+)
+            ",
+        )
+        .await?;
+        Ok(Secrets {
+            connection: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Credentials for the stream at `url`, the ones for its mount point first
+    pub async fn get(&self, guild: GuildId, url: &str) -> DbResult<Option<Credentials>> {
+        let target = match auth::target(url) {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+        let mut conn = self.connection.lock().await;
+
+        for t in &[target.as_str(), auth::target_host(&target)] {
+            let row =
+                sqlx::query("select User, Password from Secrets where Guild = ? and Target = ?")
+                    .bind(guild.0.to_string())
+                    .bind(t.to_string())
+                    .fetch_optional(&mut *conn)
+                    .await?;
+            if let Some(r) = row {
+                return Ok(Some(Credentials {
+                    user: r.try_get("User")?,
+                    password: r.try_get("Password")?,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Targets that have credentials, without the credentials
+    pub async fn list(&self, guild: GuildId) -> DbResult<Vec<String>> {
+        let mut conn = self.connection.lock().await;
+
+        let rows = sqlx::query("select Target from Secrets where Guild = ? order by Target")
+            .bind(guild.0.to_string())
+            .fetch_all(&mut *conn)
+            .await?;
+
+        rows.iter().map(|r| r.try_get("Target")).collect()
+    }
+
+    /// Saves the credentials for a target, replacing the old ones
+    pub async fn insert(&self, guild: GuildId, target: &str, auth: &Credentials) -> DbResult<()> {
+        let mut conn = self.connection.lock().await;
+
+        sqlx::query("delete from Secrets where Guild = ? and Target = ?")
+            .bind(guild.0.to_string())
+            .bind(target.to_owned())
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("insert into Secrets values (?, ?, ?, ?)")
+            .bind(guild.0.to_string())
+            .bind(target.to_owned())
+            .bind(auth.user.clone())
+            .bind(auth.password.clone())
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns false if there were no credentials for the target
+    pub async fn remove(&self, guild: GuildId, target: &str) -> DbResult<bool> {
+        let mut conn = self.connection.lock().await;
+
+        let res = sqlx::query("delete from Secrets where Guild = ? and Target = ?")
+            .bind(guild.0.to_string())
+            .bind(target.to_owned())
+            .execute(&mut *conn)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
+}
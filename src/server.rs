@@ -0,0 +1,251 @@
+use crate::{rebroadcast::stream_ogg, tap::Taps};
+use reqwest::Url;
+use serde::Deserialize;
+use serenity::{model::id::GuildId, prelude::TypeMapKey};
+use songbird::Songbird;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+use tracing::{debug, info, warn};
+
+/// Longest request accepted
+const MAX_REQUEST: usize = 8192;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Address to serve the streams on, like `0.0.0.0:8080`, disabled when unset
+    pub listen: Option<SocketAddr>,
+    /// URL the server is reachable at, for the links given out by `listen`
+    pub public_url: Option<String>,
+    /// Required as `?token=` when set
+    pub token: Option<String>,
+}
+
+impl TypeMapKey for ServerConfig {
+    type Value = ServerConfig;
+}
+
+impl ServerConfig {
+    fn base(&self) -> Option<String> {
+        Some(match &self.public_url {
+            Some(u) => u.trim_end_matches('/').to_owned(),
+            None => format!("http://{}", self.listen?),
+        })
+    }
+
+    fn link(&self, path: &str) -> Option<String> {
+        let link = format!("{}/{}", self.base()?, path);
+        Some(match &self.token {
+            Some(t) => format!("{}?{}", link, token_query(t)),
+            None => link,
+        })
+    }
+
+    /// Link to the guild's stream
+    pub fn stream_url(&self, guild: GuildId) -> Option<String> {
+        self.link(&format!("{}.ogg", guild.0))
+    }
+
+    /// Link to a cover extracted from the music library. It ends up in embeds, so it
+    /// goes without the token; the names are hashes that can't be guessed.
+    #[cfg(feature = "cache")]
+    pub fn cover_url(&self, name: &str) -> Option<String> {
+        Some(format!("{}/covers/{}", self.base()?, name))
+    }
+}
+
+/// Serves each guild's playback as Ogg Opus on `/<guild id>.ogg`,
+/// and the covers of the music library on `/covers/<name>`
+pub async fn serve(
+    config: ServerConfig,
+    taps: Arc<Taps>,
+    manager: Arc<Songbird>,
+    covers: Option<PathBuf>,
+) {
+    let addr = match config.listen {
+        Some(a) => a,
+        None => return,
+    };
+    let listener = match TcpListener::bind(addr).await {
+        Ok(l) => l,
+        Err(e) => {
+            warn!("Couldn't listen on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Serving streams on {}", addr);
+    let config = Arc::new(config);
+    let covers = Arc::new(covers);
+
+    loop {
+        let (socket, peer) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Error accepting connection: {}", e);
+                continue;
+            }
+        };
+        let (config, taps, manager) = (config.clone(), taps.clone(), manager.clone());
+        let covers = covers.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(socket, &config, &taps, &manager, covers.as_deref()).await {
+                debug!("Stream to {} ended: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// `token=<t>`, percent-encoded
+fn token_query(token: &str) -> String {
+    let mut url = Url::parse("http://localhost/").unwrap();
+    url.query_pairs_mut().append_pair("token", token);
+    url.query().unwrap_or_default().to_owned()
+}
+
+/// The decoded `token` parameter of a query string
+fn given_token(query: &str) -> Option<String> {
+    let mut url = Url::parse("http://localhost/").ok()?;
+    url.set_query(Some(query));
+    url.query_pairs()
+        .find(|(k, _)| k == "token")
+        .map(|(_, v)| v.into_owned())
+}
+
+async fn handle(
+    mut socket: TcpStream,
+    config: &ServerConfig,
+    taps: &Taps,
+    manager: &Songbird,
+    covers: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = timeout(Duration::from_secs(10), socket.read(&mut buf)).await??;
+        if n == 0 || request.len() > MAX_REQUEST {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut line = request.lines().next().unwrap_or_default().split(' ');
+    let (method, target) = (
+        line.next().unwrap_or_default(),
+        line.next().unwrap_or_default(),
+    );
+
+    let (path, query) = match target.find('?') {
+        Some(i) => (&target[..i], &target[i + 1..]),
+        None => (target, ""),
+    };
+    if method != "GET" && method != "HEAD" {
+        return respond(&mut socket, "405 Method Not Allowed").await;
+    }
+    // Covers are shown in embeds, the token would leak
+    if let Some(name) = path.strip_prefix("/covers/") {
+        return cover(&mut socket, covers, name, method == "HEAD").await;
+    }
+    if let Some(t) = &config.token {
+        if given_token(query).as_deref() != Some(t.as_str()) {
+            return respond(&mut socket, "403 Forbidden").await;
+        }
+    }
+
+    // Only guilds with a voice connection have a stream
+    let guild = match path
+        .trim_start_matches('/')
+        .trim_end_matches(".ogg")
+        .parse::<u64>()
+        .ok()
+        .map(GuildId)
+        .filter(|g| manager.get(*g).is_some())
+    {
+        Some(g) => g,
+        None => return respond(&mut socket, "404 Not Found").await,
+    };
+
+    socket
+        .write_all(
+            b"HTTP/1.0 200 OK\r\n\
+              Content-Type: audio/ogg\r\n\
+              Cache-Control: no-cache, no-store\r\n\
+              Connection: close\r\n\
+              \r\n",
+        )
+        .await?;
+    if method == "HEAD" {
+        return Ok(());
+    }
+
+    debug!("Streaming guild {}", guild);
+    let mut rx = taps.subscribe(guild);
+    stream_ogg(
+        &mut socket,
+        &mut rx,
+        manager,
+        guild,
+        futures::future::pending::<()>(),
+    )
+    .await
+}
+
+/// Sends a cover from the covers directory, only plain file names are looked up
+async fn cover(
+    socket: &mut TcpStream,
+    dir: Option<&Path>,
+    name: &str,
+    head: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let valid =
+        name.split('.').count() == 2 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.');
+    let data = match dir.filter(|_| valid) {
+        Some(d) => tokio::fs::read(d.join(name)).await.ok(),
+        None => None,
+    };
+    let data = match data {
+        Some(d) => d,
+        None => return respond(socket, "404 Not Found").await,
+    };
+    let content_type = if name.ends_with(".png") {
+        "image/png"
+    } else {
+        "image/jpeg"
+    };
+
+    let header = format!(
+        "HTTP/1.0 200 OK\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Cache-Control: max-age=86400\r\n\
+         Connection: close\r\n\
+         \r\n",
+        content_type,
+        data.len()
+    );
+    socket.write_all(header.as_bytes()).await?;
+    if !head {
+        socket.write_all(&data).await?;
+    }
+    Ok(())
+}
+
+async fn respond(
+    socket: &mut TcpStream,
+    status: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let response = format!(
+        "HTTP/1.0 {}\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\n{}\n",
+        status, status
+    );
+    socket.write_all(response.as_bytes()).await?;
+    Ok(())
+}
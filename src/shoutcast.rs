@@ -0,0 +1,183 @@
+use crate::{
+    icy,
+    station::{SourceStatus, StationInfo, StationServer},
+};
+use chrono::{Duration, Utc};
+use http::Uri;
+use serde_json::Value;
+
+const THUMBNAIL: &str = "https://www.shoutcast.com/img/shoutcast-logo.png";
+
+/// Stream id, from `?sid=2` or `/stream/2/` in the stream URL
+fn stream_id(uri: &Uri) -> u64 {
+    uri.query()
+        .and_then(|q| {
+            q.split('&')
+                .find_map(|p| p.strip_prefix("sid="))
+                .and_then(|s| s.parse().ok())
+        })
+        .or_else(|| {
+            uri.path()
+                .strip_prefix("/stream/")
+                .and_then(|p| p.split('/').next())
+                .and_then(|s| s.parse().ok())
+        })
+        .unwrap_or(1)
+}
+
+/// Shoutcast v2's `/stats?sid=1&json=1`
+pub struct ShoutcastV2;
+
+impl StationServer for ShoutcastV2 {
+    fn name(&self) -> &'static str {
+        "Shoutcast v2"
+    }
+
+    fn status_url(&self, uri: &Uri) -> Option<String> {
+        Some(format!(
+            "{}://{}/stats?sid={}&json=1",
+            uri.scheme_str()?,
+            uri.authority()?,
+            stream_id(uri)
+        ))
+    }
+
+    fn parse(&self, body: &str, _: &str) -> Option<SourceStatus> {
+        let value: Value = serde_json::from_str(body).ok()?;
+        let obj = value.as_object()?;
+        // Don't mistake any JSON for a Shoutcast status
+        obj.get("streamstatus")?;
+
+        let string = |key| {
+            obj.get(key)
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_owned)
+        };
+        let number = |key| {
+            obj.get(key)
+                .and_then(|v| v.as_u64().or_else(|| v.as_str().and_then(|s| s.parse().ok())))
+        };
+
+        let (title, artist) = string("songtitle")
+            .map(|t| icy::split_title(&t))
+            .unwrap_or_default();
+
+        Some(SourceStatus {
+            title,
+            artist,
+            sample_rate: number("samplerate").map(|r| r as u32),
+            station: StationInfo {
+                name: string("servertitle"),
+                genre: string("servergenre"),
+                description: string("serverurl"),
+                listeners: number("currentlisteners"),
+                bitrate: number("bitrate"),
+                started: number("streamuptime")
+                    .map(|u| Utc::now() - Duration::seconds(u as i64)),
+            },
+            ..Default::default()
+        })
+    }
+
+    fn thumbnail(&self) -> Option<&'static str> {
+        Some(THUMBNAIL)
+    }
+}
+
+/// Shoutcast v1's `/7.html`, a single line like
+/// `<html><body>listeners,status,peak,max,unique,bitrate,song</body></html>`
+pub struct ShoutcastV1;
+
+impl StationServer for ShoutcastV1 {
+    fn name(&self) -> &'static str {
+        "Shoutcast v1"
+    }
+
+    fn status_url(&self, uri: &Uri) -> Option<String> {
+        Some(format!(
+            "{}://{}/7.html",
+            uri.scheme_str()?,
+            uri.authority()?
+        ))
+    }
+
+    fn parse(&self, body: &str, _: &str) -> Option<SourceStatus> {
+        let start = body.find("<body>")? + "<body>".len();
+        let end = body[start..].find("</body>")? + start;
+        // The song title can contain commas
+        let fields: Vec<&str> = body[start..end].splitn(7, ',').collect();
+        if fields.len() != 7 {
+            return None;
+        }
+        let numbers = fields[..6]
+            .iter()
+            .map(|f| f.trim().parse::<u64>().ok())
+            .collect::<Option<Vec<u64>>>()?;
+
+        let (title, artist) = icy::split_title(fields[6]);
+
+        Some(SourceStatus {
+            title,
+            artist,
+            station: StationInfo {
+                listeners: Some(numbers[0]),
+                bitrate: Some(numbers[5]),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    fn thumbnail(&self) -> Option<&'static str> {
+        Some(THUMBNAIL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v2() {
+        let body = include_str!("../tests/fixtures/shoutcast/stats.json");
+        let status = ShoutcastV2.parse(body, "").unwrap();
+        assert_eq!(status.title.as_deref(), Some("So What"));
+        assert_eq!(status.artist.as_deref(), Some("Miles Davis"));
+        assert_eq!(status.sample_rate, Some(44100));
+        let station = status.station;
+        assert_eq!(station.name.as_deref(), Some("Jazz Lounge"));
+        assert_eq!(station.genre.as_deref(), Some("Jazz"));
+        assert_eq!(station.listeners, Some(12));
+        assert_eq!(station.bitrate, Some(128));
+        let uptime = Utc::now() - station.started.unwrap();
+        assert!((3599..3700).contains(&uptime.num_seconds()));
+
+        // Other JSON isn't a Shoutcast status
+        assert!(ShoutcastV2.parse(r#"{"icestats":{}}"#, "").is_none());
+    }
+
+    #[test]
+    fn v1() {
+        let body = include_str!("../tests/fixtures/shoutcast/7.html");
+        let status = ShoutcastV1.parse(body, "").unwrap();
+        assert_eq!(status.title.as_deref(), Some("Song"));
+        assert_eq!(status.artist.as_deref(), Some("Artist, With Comma"));
+        assert_eq!(status.station.listeners, Some(12));
+        assert_eq!(status.station.bitrate, Some(128));
+
+        assert!(ShoutcastV1
+            .parse("<html><body>12,1,40</body></html>", "")
+            .is_none());
+        assert!(ShoutcastV1.parse("<html>Not found</html>", "").is_none());
+    }
+
+    #[test]
+    fn stream_ids() {
+        let id = |u: &str| stream_id(&u.parse().unwrap());
+        assert_eq!(id("http://radio.example.com:8000/"), 1);
+        assert_eq!(id("http://radio.example.com:8000/;?sid=3"), 3);
+        assert_eq!(id("http://radio.example.com:8000/stream/2/"), 2);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{
+    auth::{self, Credentials},
+    icecast::Icecast,
+    playlist::Format,
+    station,
+};
+use reqwest::{
+    header::{HeaderMap, CONTENT_TYPE, RANGE},
+    RequestBuilder, Response,
+};
+use std::time::Duration;
+use tracing::debug;
+
+/// How long a server gets to answer before the link is left to ytdl
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a link serves, going by its headers and the server's status page
+#[derive(Debug, Clone, Default)]
+pub struct Sniffed {
+    /// Lowercase, without parameters
+    pub content_type: Option<String>,
+    /// `icy-*` or `ice-*` headers were sent back
+    pub icy: bool,
+    /// The stream is listed on the server's `status-json.xsl`
+    pub icecast: bool,
+}
+
+impl Sniffed {
+    /// Audio ffmpeg can play as is
+    pub fn is_audio(&self) -> bool {
+        match self.content_type.as_deref() {
+            Some(t) => t.starts_with("audio/") || t == "application/ogg",
+            None => false,
+        }
+    }
+
+    pub fn is_playlist(&self) -> bool {
+        self.content_type
+            .as_deref()
+            .and_then(Format::from_content_type)
+            .is_some()
+    }
+
+    /// A radio, with metadata from the Icecast path
+    pub fn is_station(&self) -> bool {
+        self.icy || self.icecast
+    }
+}
+
+/// Asks the server what's at `url` with a HEAD request, or the first byte of a GET for
+/// servers that don't take HEAD. `None` if it doesn't answer either.
+pub async fn sniff(url: &str, auth: Option<&Credentials>) -> Option<Sniffed> {
+    let response = match head(url, auth).await {
+        Some(r) => r,
+        None => partial_get(url, auth).await?,
+    };
+    let headers = response.headers();
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|c| c.to_str().ok())
+        .and_then(|c| c.split(';').next())
+        .map(|c| c.trim().to_ascii_lowercase());
+    let icy = has_icy(headers);
+    // Dropping the response closes the connection, it could be an endless stream
+    drop(response);
+
+    let mut sniffed = Sniffed {
+        content_type,
+        icy,
+        icecast: false,
+    };
+    // Only streams are worth a look at the status page
+    if sniffed.icy || sniffed.is_audio() {
+        sniffed.icecast = station::fetch(&Icecast, url, None, auth).await.is_ok();
+    }
+    debug!("Sniffed {}: {:?}", url, sniffed);
+    Some(sniffed)
+}
+
+fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .unwrap_or_default()
+}
+
+async fn head(url: &str, auth: Option<&Credentials>) -> Option<Response> {
+    send(client().head(url), url, auth).await
+}
+
+async fn partial_get(url: &str, auth: Option<&Credentials>) -> Option<Response> {
+    send(client().get(url).header(RANGE, "bytes=0-0"), url, auth).await
+}
+
+/// Sends the request asking for in-band metadata, like players do
+async fn send(request: RequestBuilder, url: &str, auth: Option<&Credentials>) -> Option<Response> {
+    let request = auth::basic_auth(request.header("Icy-MetaData", "1"), auth);
+    match request.send().await {
+        Ok(r) if r.status().is_success() => Some(r),
+        Ok(r) => {
+            debug!("{} answered {}", url, r.status());
+            None
+        }
+        Err(e) => {
+            debug!("Couldn't reach {}: {}", url, e);
+            None
+        }
+    }
+}
+
+fn has_icy(headers: &HeaderMap) -> bool {
+    headers.keys().any(|k| {
+        let name = k.as_str();
+        name.starts_with("icy-") || name.starts_with("ice-")
+    })
+}
@@ -0,0 +1,162 @@
+use crate::{
+    auth::{self, Credentials},
+    icecast::{Icecast, IcecastAdmin, IcecastStatusPage},
+    shoutcast::{ShoutcastV1, ShoutcastV2},
+};
+use chrono::{DateTime, Utc};
+use http::Uri;
+use songbird::input::Metadata;
+use std::error::Error;
+use tracing::debug;
+
+/// A radio server with a status page describing its streams
+pub trait StationServer: Send + Sync {
+    /// Shown to users
+    fn name(&self) -> &'static str;
+    /// Status page for the stream at `uri`
+    fn status_url(&self, uri: &Uri) -> Option<String>;
+    /// Reads the status of the stream at `query`, `None` if the page isn't in this
+    /// server's format
+    fn parse(&self, body: &str, query: &str) -> Option<SourceStatus>;
+    fn thumbnail(&self) -> Option<&'static str> {
+        None
+    }
+    /// Whether the status page needs the admin credentials from the config
+    fn admin(&self) -> bool {
+        false
+    }
+}
+
+pub type Server = &'static dyn StationServer;
+
+/// Known server types, in detection order
+pub static SERVERS: &[Server] = &[
+    &Icecast,
+    &IcecastAdmin,
+    &IcecastStatusPage,
+    &ShoutcastV2,
+    &ShoutcastV1,
+];
+
+/// Details on a radio station, shown in `np`
+#[derive(Debug, Default, Clone)]
+pub struct StationInfo {
+    pub name: Option<String>,
+    pub genre: Option<String>,
+    pub description: Option<String>,
+    pub listeners: Option<u64>,
+    /// kbit/s
+    pub bitrate: Option<u64>,
+    /// When the stream went live
+    pub started: Option<DateTime<Utc>>,
+}
+
+impl StationInfo {
+    /// Overwrites the fields that are set in `other`
+    pub fn merge(&mut self, other: StationInfo) {
+        self.name = other.name.or(self.name.take());
+        self.genre = other.genre.or(self.genre.take());
+        self.description = other.description.or(self.description.take());
+        self.listeners = other.listeners.or(self.listeners);
+        self.bitrate = other.bitrate.or(self.bitrate);
+        self.started = other.started.or(self.started);
+    }
+}
+
+/// Current state of a stream, as reported by the server's status page
+#[derive(Debug, Default, Clone)]
+pub struct SourceStatus {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub channels: Option<u8>,
+    pub sample_rate: Option<u32>,
+    pub station: StationInfo,
+}
+
+impl SourceStatus {
+    /// For streams without a status page, the host stands in for the station name
+    pub fn minimal(query: &str) -> Self {
+        Self {
+            station: StationInfo {
+                name: query
+                    .parse::<Uri>()
+                    .ok()
+                    .and_then(|u| u.host().map(str::to_owned)),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn into_metadata(self, query: &str, thumbnail: Option<&str>) -> Metadata {
+        let date = self.station.started.map(|d| d.to_rfc3339());
+        // The station takes the place of the title until a song is known,
+        // and of the artist ("channel") otherwise
+        let (title, artist) = match self.title {
+            Some(t) => (Some(t), self.artist.or(self.station.name)),
+            None => (self.station.name, None),
+        };
+
+        Metadata {
+            title,
+            artist,
+            date,
+            channels: self.channels,
+            start_time: None,
+            duration: None,
+            sample_rate: self.sample_rate,
+            source_url: Some(query.to_owned()),
+            thumbnail: thumbnail.map(str::to_owned),
+        }
+    }
+}
+
+/// Shoutcast v1 only answers with its status page to browsers
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .user_agent("Mozilla/5.0 (compatible; sodmb)")
+        .build()
+        .unwrap_or_default()
+}
+
+/// Admin username and password
+pub type Admin<'a> = Option<(&'a str, &'a str)>;
+
+/// Fetches the status of the stream at `query`, with `auth` being the credentials of the
+/// stream itself, used unless the page needs the admin ones
+pub async fn fetch(
+    server: Server,
+    query: &str,
+    admin: Admin<'_>,
+    auth: Option<&Credentials>,
+) -> Result<SourceStatus, Box<dyn Error + Send + Sync>> {
+    let uri: Uri = query.parse()?;
+    let url = server.status_url(&uri).ok_or("Invalid stream URL")?;
+    let mut request = client().get(&url);
+    if server.admin() {
+        let (user, password) = admin.ok_or("No admin credentials")?;
+        request = request.basic_auth(user, Some(password));
+    } else {
+        request = auth::basic_auth(request, auth);
+    }
+    let body = request.send().await?.error_for_status()?.text().await?;
+
+    server
+        .parse(&body, query)
+        .ok_or_else(|| format!("Not a {} status page", server.name()).into())
+}
+
+/// Finds out which kind of server the stream is on by trying each status page
+pub async fn detect(
+    query: &str,
+    admin: Admin<'_>,
+    auth: Option<&Credentials>,
+) -> Option<(Server, SourceStatus)> {
+    for server in SERVERS {
+        match fetch(*server, query, admin, auth).await {
+            Ok(s) => return Some((*server, s)),
+            Err(e) => debug!("{} is not on {}: {}", query, server.name(), e),
+        }
+    }
+    None
+}
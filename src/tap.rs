@@ -0,0 +1,203 @@
+use audiopus::{coder::Encoder, Application, Bitrate, Channels, SampleRate};
+use serenity::{model::id::GuildId, prelude::TypeMapKey};
+use songbird::input::{Codec, Container, Input, Reader};
+use std::{
+    collections::HashMap,
+    io::{self, Read, Seek, SeekFrom},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// 20ms of audio at 48kHz
+const FRAME_SAMPLES: usize = 960;
+/// Packets buffered for slow listeners, about 5 seconds
+const CAPACITY: usize = 256;
+const BITRATE: i32 = 128_000;
+
+/// An Opus packet, 20ms of 48kHz audio
+pub type Packet = Arc<[u8]>;
+
+/// Copies of the audio the driver reads for each guild, for recordings and
+/// streams outside of Discord
+#[derive(Default)]
+pub struct Taps(Mutex<HashMap<GuildId, broadcast::Sender<Packet>>>);
+
+impl TypeMapKey for Taps {
+    type Value = Arc<Taps>;
+}
+
+impl Taps {
+    fn sender(&self, guild: GuildId) -> broadcast::Sender<Packet> {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(guild)
+            .or_insert_with(|| broadcast::channel(CAPACITY).0)
+            .clone()
+    }
+
+    /// Receives the packets of the tracks played in `guild` from now on
+    pub fn subscribe(&self, guild: GuildId) -> broadcast::Receiver<Packet> {
+        self.sender(guild).subscribe()
+    }
+
+    /// Makes the input copy what gets played to the guild's listeners
+    ///
+    /// Opus in DCA frames, like compressed, cached and ranged Opus tracks, is copied
+    /// frame by frame as it is. Only PCM sources get encoded for the copy, and only
+    /// while someone is listening.
+    pub fn wrap(&self, guild: GuildId, mut input: Input) -> Input {
+        let framing = match (&input.kind, &input.container) {
+            (Codec::Opus(_), Container::Dca { .. }) => Framing::Dca,
+            // songbird can't play unframed Opus either
+            (Codec::Opus(_), _) => return input,
+            (Codec::Pcm, _) => Framing::Pcm,
+            (Codec::FloatPcm, _) => Framing::FloatPcm,
+        };
+        let channels = if input.stereo { 2 } else { 1 };
+        let seekable = input.reader.is_seekable();
+        let reader = std::mem::replace(&mut input.reader, Reader::from(Vec::new()));
+
+        let tap = TapReader {
+            inner: reader,
+            tx: self.sender(guild),
+            framing,
+            channels,
+            pending: Vec::new(),
+            encoder: None,
+        };
+        input.reader = if seekable {
+            Reader::ExtensionSeek(Box::new(tap))
+        } else {
+            Reader::Extension(Box::new(tap))
+        };
+        input
+    }
+}
+
+enum Framing {
+    /// i16 length + Opus packet, forwarded as is
+    Dca,
+    /// i16 samples, encoded when someone listens
+    Pcm,
+    /// f32 samples, encoded when someone listens
+    FloatPcm,
+}
+
+struct TapReader {
+    inner: Reader,
+    tx: broadcast::Sender<Packet>,
+    framing: Framing,
+    channels: usize,
+    /// Bytes read that don't make a whole packet or frame yet
+    pending: Vec<u8>,
+    encoder: Option<Encoder>,
+}
+
+impl TapReader {
+    fn frame_bytes(&self) -> usize {
+        let sample = match self.framing {
+            Framing::Pcm => 2,
+            _ => 4,
+        };
+        FRAME_SAMPLES * self.channels * sample
+    }
+
+    /// Sends out every whole packet in `pending`. The bytes are kept track of even
+    /// without listeners so packet boundaries are known when one shows up.
+    fn flush(&mut self) {
+        loop {
+            let listening = self.tx.receiver_count() > 0;
+            let packet = match self.framing {
+                Framing::Dca => {
+                    if self.pending.len() < 2 {
+                        return;
+                    }
+                    let len = i16::from_le_bytes([self.pending[0], self.pending[1]]).max(0);
+                    let end = 2 + len as usize;
+                    if self.pending.len() < end {
+                        return;
+                    }
+                    let packet = if listening {
+                        Some(Packet::from(&self.pending[2..end]))
+                    } else {
+                        None
+                    };
+                    self.pending.drain(..end);
+                    packet
+                }
+                Framing::Pcm | Framing::FloatPcm => {
+                    let end = self.frame_bytes();
+                    if self.pending.len() < end {
+                        return;
+                    }
+                    let packet = if listening { self.encode(end) } else { None };
+                    self.pending.drain(..end);
+                    packet
+                }
+            };
+            if let Some(p) = packet {
+                let _ = self.tx.send(p);
+            }
+        }
+    }
+
+    fn encode(&mut self, len: usize) -> Option<Packet> {
+        let samples: Vec<f32> = match self.framing {
+            Framing::Pcm => self.pending[..len]
+                .chunks_exact(2)
+                .map(|s| i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0)
+                .collect(),
+            _ => self.pending[..len]
+                .chunks_exact(4)
+                .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+                .collect(),
+        };
+
+        if self.encoder.is_none() {
+            let channels = if self.channels == 2 {
+                Channels::Stereo
+            } else {
+                Channels::Mono
+            };
+            match Encoder::new(SampleRate::Hz48000, channels, Application::Audio) {
+                Ok(mut e) => {
+                    let _ = e.set_bitrate(Bitrate::BitsPerSecond(BITRATE));
+                    self.encoder = Some(e);
+                }
+                Err(e) => {
+                    warn!("Error creating Opus encoder: {:?}", e);
+                    return None;
+                }
+            }
+        }
+
+        let mut out = [0u8; 4000];
+        match self.encoder.as_ref()?.encode_float(&samples, &mut out) {
+            Ok(n) => Some(Packet::from(&out[..n])),
+            Err(e) => {
+                warn!("Error encoding tapped audio: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Read for TapReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pending.extend_from_slice(&buf[..n]);
+        self.flush();
+        Ok(n)
+    }
+}
+
+impl Seek for TapReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        // Seeks land on packet boundaries
+        self.pending.clear();
+        self.encoder = None;
+        self.inner.seek(pos)
+    }
+}
@@ -0,0 +1,127 @@
+use serde::Deserialize;
+use serde_json::Value;
+use serenity::prelude::TypeMapKey;
+use songbird::input::Metadata;
+use std::{error::Error, time::Duration};
+use tokio::process::Command;
+use tracing::debug;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PlaylistConfig {
+    /// Entries queued at most from a single playlist
+    pub max_entries: usize,
+}
+
+impl Default for PlaylistConfig {
+    fn default() -> Self {
+        Self { max_entries: 50 }
+    }
+}
+
+impl TypeMapKey for PlaylistConfig {
+    type Value = PlaylistConfig;
+}
+
+pub struct Playlist {
+    pub title: Option<String>,
+    /// Only what the listing has, `source_url` is always set
+    pub entries: Vec<Metadata>,
+}
+
+/// What a link points at, going by youtube-dl's metadata
+pub enum Listing {
+    Playlist(Playlist),
+    /// A single video, with all of its metadata
+    Video(Metadata),
+}
+
+/// Lists the entries of a playlist link without resolving them, or gets the metadata
+/// of a video without opening its audio. Links with both a video and a list are the
+/// video, like `--no-playlist`.
+pub async fn list(url: &str, max: usize) -> Result<Listing, Box<dyn Error + Send + Sync>> {
+    let output = Command::new("youtube-dl")
+        .args(&[
+            "-J",
+            "--flat-playlist",
+            "--no-playlist",
+            "--ignore-config",
+            "--no-warnings",
+            "--playlist-end",
+            &max.to_string(),
+            url,
+        ])
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().into());
+    }
+
+    let value: Value = serde_json::from_slice(&output.stdout)?;
+    if value.get("_type").and_then(Value::as_str) != Some("playlist") {
+        let mut meta = Metadata::from_ytdl_output(value);
+        if meta.source_url.is_none() {
+            meta.source_url = Some(url.to_owned());
+        }
+        return Ok(Listing::Video(meta));
+    }
+    let string = |v: &Value, key| v.get(key).and_then(Value::as_str).map(str::to_owned);
+
+    let entries = value
+        .get("entries")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|e| {
+            let url = entry_url(e);
+            if url.is_none() {
+                debug!("Skipping playlist entry without URL: {}", e);
+            }
+            Some(Metadata {
+                title: string(e, "title"),
+                artist: string(e, "uploader").or_else(|| string(e, "channel")),
+                duration: e
+                    .get("duration")
+                    .and_then(Value::as_f64)
+                    .map(Duration::from_secs_f64),
+                channels: Some(2),
+                source_url: Some(url?),
+                ..Default::default()
+            })
+        })
+        .take(max)
+        .collect();
+
+    Ok(Listing::Playlist(Playlist {
+        title: string(&value, "title"),
+        entries,
+    }))
+}
+
+/// The first `count` results of a YouTube search
+pub async fn search(
+    query: &str,
+    count: usize,
+) -> Result<Vec<Metadata>, Box<dyn Error + Send + Sync>> {
+    match list(&format!("ytsearch{}:{}", count, query), count).await? {
+        Listing::Playlist(p) => Ok(p.entries),
+        Listing::Video(_) => Ok(Vec::new()),
+    }
+}
+
+/// Flat entries from YouTube only have the video id as URL
+fn entry_url(entry: &Value) -> Option<String> {
+    let url = entry.get("url").and_then(Value::as_str);
+    match url {
+        Some(u) if u.starts_with("http") => Some(u.to_owned()),
+        Some(id) if entry.get("ie_key").and_then(Value::as_str) == Some("Youtube") => {
+            Some(format!("https://www.youtube.com/watch?v={}", id))
+        }
+        _ => entry
+            .get("webpage_url")
+            .and_then(Value::as_str)
+            .map(str::to_owned),
+    }
+}
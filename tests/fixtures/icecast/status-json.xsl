@@ -0,0 +1,46 @@
+{
+  "icestats": {
+    "admin": "icemaster@localhost",
+    "host": "radio.example.com",
+    "location": "Earth",
+    "server_id": "Icecast 2.4.4",
+    "server_start_iso8601": "2021-04-05T08:00:00+0000",
+    "source": [
+      {
+        "audio_info": "channels=2;samplerate=44100;bitrate=128",
+        "bitrate": 128,
+        "channels": 2,
+        "genre": "Jazz",
+        "listener_peak": 40,
+        "listeners": 12,
+        "listenurl": "http://localhost:8000/jazz.mp3",
+        "samplerate": 44100,
+        "server_description": "Smooth jazz all day",
+        "server_name": "Jazz Lounge",
+        "server_type": "audio/mpeg",
+        "stream_start": "Mon, 05 Apr 2021 12:30:00 +0200",
+        "stream_start_iso8601": "2021-04-05T12:30:00+0200",
+        "title": "Miles Davis - So What",
+        "dummy": null
+      },
+      {
+        "artist": "The Hosts",
+        "audio_bitrate": 96000,
+        "channels": "1",
+        "genre": "Talk",
+        "listeners": "3",
+        "listenurl": "http://localhost:8000/talk.ogg",
+        "samplerate": "48000",
+        "server_name": "Talk Radio",
+        "server_type": "application/ogg",
+        "title": "Morning Show"
+      },
+      {
+        "listeners": 0,
+        "listenurl": "http://localhost:8000/quiet",
+        "server_name": "  ",
+        "title": ""
+      }
+    ]
+  }
+}
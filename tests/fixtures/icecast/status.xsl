@@ -0,0 +1,42 @@
+<!DOCTYPE html>
+<html>
+<head><title>Icecast Streaming Media Server</title></head>
+<body>
+<h2>Icecast2 Status</h2>
+<div class="roundbox">
+  <div class="mounthead">
+    <h3 class="mount">Mount Point /jazz.mp3</h3>
+    <div class="right"><ul class="mountlist"><li><a class="play" href="/jazz.mp3.m3u">M3U</a></li></ul></div>
+  </div>
+  <div class="mountcont">
+    <table class="yellowkeys">
+      <tbody>
+        <tr><td>Stream Name:</td><td>Jazz &amp; Blues Lounge</td></tr>
+        <tr><td>Stream Description:</td><td>Smooth jazz, all &quot;day&quot;</td></tr>
+        <tr><td>Content Type:</td><td>audio/mpeg</td></tr>
+        <tr><td>Stream started:</td><td class="streamstats">Mon, 05 Apr 2021 12:30:00 +0200</td></tr>
+        <tr><td>Bitrate:</td><td class="streamstats">128</td></tr>
+        <tr><td>Listeners (current):</td><td class="streamstats">12</td></tr>
+        <tr><td>Genre:</td><td class="streamstats">Jazz</td></tr>
+        <tr><td>Currently playing:</td><td class="streamstats">Miles Davis - So What</td></tr>
+      </tbody>
+    </table>
+  </div>
+</div>
+<div class="roundbox">
+  <div class="mounthead">
+    <h3 class="mount">Mount Point /talk.ogg</h3>
+  </div>
+  <div class="mountcont">
+    <table class="yellowkeys">
+      <tbody>
+        <tr><td>Stream Title:</td><td><a href="http://talk.example.com">Talk &lt;Live&gt;</a></td></tr>
+        <tr><td>Current Listeners:</td><td class="streamstats">3</td></tr>
+        <tr><td>Current Song:</td><td class="streamstats">The Hosts - Rock &#39;n&#39; Roll</td></tr>
+      </tbody>
+    </table>
+  </div>
+</div>
+<div id="footer">Support icecast development at <a href="https://www.icecast.org">www.icecast.org</a></div>
+</body>
+</html>